@@ -1,19 +1,43 @@
+/// A range of bytes in the source of a module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Module {
     pub name: String,
     pub body: Vec<Statement>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum AssignmentTarget {
     Identifier(String),
     Property(Expression, String),
     Subscript(Expression, Expression),
 }
 
-#[derive(Debug)]
-pub enum Statement {
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum StatementKind {
     Expression(Expression),
     Return(Expression),
     Assignment {
@@ -27,11 +51,28 @@ pub enum Statement {
     If {
         cond: Expression,
         body: Vec<Statement>,
-        else_body: Vec<Statement>
-    }
+        else_body: Vec<Statement>,
+    },
+    While {
+        cond: Expression,
+        body: Vec<Statement>,
+    },
+    For {
+        ident: String,
+        iterable: Expression,
+        body: Vec<Statement>,
+    },
+    Break,
+    Continue,
+    Throw(Expression),
+    Try {
+        body: Vec<Statement>,
+        catch: Option<(String, Vec<Statement>)>,
+        finally: Option<Vec<Statement>>,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Operator {
     Add,
     Subtract,
@@ -43,14 +84,48 @@ pub enum Operator {
     GreaterThan,
     LessThanOrEqual,
     GreaterThanOrEqual,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone)]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
 }
 
-#[derive(Debug)]
-pub enum Expression {
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// Create a binary operation spanning both operands.
+    pub fn operation(lhs: Expression, op: Operator, rhs: Expression) -> Self {
+        let span = Span::new(lhs.span.start, rhs.span.end);
+        let kind = ExpressionKind::Operation {
+            lhs: Box::new(lhs),
+            op,
+            rhs: Box::new(rhs),
+        };
+
+        Self::new(kind, span)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ExpressionKind {
     Identifier(String),
     Integer(i64),
     Float(f64),
     String(String),
+    Bool(bool),
+    Null,
     List(Vec<Expression>),
     Object(Vec<(String, Expression)>),
     Property(Box<Expression>, String),
@@ -68,4 +143,8 @@ pub enum Expression {
         op: Operator,
         rhs: Box<Expression>,
     },
+    Unary {
+        op: UnaryOperator,
+        expr: Box<Expression>,
+    },
 }
@@ -1,7 +1,12 @@
+use std::{collections::HashMap, fmt};
+
+pub(crate) use self::types::iterator_method;
 use self::types::BuiltinTypes;
 use crate::{
-    get_native_prop,
-    value::{ArgPattern, Value},
+    error::{ErrorKind, RuntimeError},
+    gc, get_native_prop,
+    ptr::{Ptr, PtrMut},
+    value::{ArgPattern, Object, Value},
     Interpreter,
 };
 
@@ -11,6 +16,13 @@ mod types;
 pub struct Builtins {
     pub types: BuiltinTypes,
     pub print: Value,
+    pub error: Value,
+    /// `exit(code)` ends the run, see `RuntimeError::exit_code`.
+    pub exit: Value,
+    /// Sentinel returned by `$next` when an iterator is exhausted.
+    pub stop: Value,
+    /// The cycle collector, `gc.collect()` and `gc.stats()`.
+    pub gc: Value,
 }
 
 macro_rules! impl_builtin_names {
@@ -25,16 +37,57 @@ macro_rules! impl_builtin_names {
 impl Builtins {
     /// Create new instance of builtins.
     pub fn new() -> Self {
+        let types = BuiltinTypes::new();
+        let stop = Object::new(types.stop.clone(), HashMap::new());
+
+        let mut gc = HashMap::new();
+        gc.insert(
+            Ptr::new("collect".to_string()),
+            Value::Native(gc_collect, ArgPattern::Exact(1)),
+        );
+        gc.insert(
+            Ptr::new("stats".to_string()),
+            Value::Native(gc_stats, ArgPattern::Exact(1)),
+        );
+        let gc = Object::new(types.object.clone(), gc);
+
         Self {
-            types: BuiltinTypes::new(),
+            types,
             print: Value::Native(print, ArgPattern::Any),
+            error: Value::Native(error, ArgPattern::Exact(1)),
+            exit: Value::Native(exit, ArgPattern::Max(1)),
+            stop: Value::Object(PtrMut::new(stop)),
+            gc: Value::Object(PtrMut::new(gc)),
         }
     }
 
     /// Get a builtin by name.
     /// Returns `None` if `name` does not exist.
     pub fn resolve(&self, name: &str) -> Option<Value> {
-        impl_builtin_names!(self, name, [print])
+        impl_builtin_names!(self, name, [print, error, exit, stop, gc])
+    }
+
+    /// Create an error object of type `kind` with `message`.
+    pub fn error_object(&self, kind: &str, message: &str) -> Value {
+        let mut properties = HashMap::new();
+        properties.insert(
+            Ptr::new("type".to_string()),
+            Value::String(Ptr::new(kind.to_string())),
+        );
+        properties.insert(
+            Ptr::new("message".to_string()),
+            Value::String(Ptr::new(message.to_string())),
+        );
+
+        Value::new_object(Object::new(self.types.error.clone(), properties))
+    }
+
+    /// Whether `value` is the `stop` sentinel.
+    pub fn is_stop(&self, value: &Value) -> bool {
+        match (value, &self.stop) {
+            (Value::Object(value), Value::Object(stop)) => value.id() == stop.id(),
+            _ => false,
+        }
     }
 }
 
@@ -44,23 +97,79 @@ impl Default for Builtins {
     }
 }
 
-fn print(interp: &mut Interpreter, args: &[Value]) -> Value {
+fn print(interp: &mut Interpreter, args: &[Value]) -> Result<Value, RuntimeError> {
     for (i, arg) in args.iter().enumerate() {
         let display = get_native_prop!(interp, arg, display);
-        if let Value::String(str) = interp.call_with_return(display, &[arg.clone()]) {
+        if let Value::String(str) = interp.call_with_return(display, std::slice::from_ref(arg))? {
             if i == 0 {
                 print!("{}", str.value());
             } else {
                 print!(", {}", str.value());
             }
         } else {
-            panic!("expected `$display` to return str");
+            return Err(RuntimeError::new(
+                ErrorKind::Type,
+                "expected `$display` to return str",
+            ));
         }
     }
 
     println!();
 
-    Value::Null
+    Ok(Value::Null)
+}
+
+fn error(interp: &mut Interpreter, args: &[Value]) -> Result<Value, RuntimeError> {
+    let message = args[0].string(interp)?;
+    let kind = ErrorKind::Error.name();
+
+    Ok(interp.builtins().error_object(kind, message.value()))
 }
 
+fn exit(interp: &mut Interpreter, args: &[Value]) -> Result<Value, RuntimeError> {
+    let code = match args.first() {
+        Some(code) => code.int(interp)?,
+        None => 0,
+    };
+
+    Err(exit_error(code))
+}
+
+/// The error ending a run with `code`, a `ValueError` if the code doesn't
+/// fit in 32 bits.
+pub(crate) fn exit_error<T: TryInto<i32> + Copy + fmt::Display>(code: T) -> RuntimeError {
+    match code.try_into() {
+        Ok(code) => RuntimeError::exit(code),
+        Err(_) => RuntimeError::new(
+            ErrorKind::Value,
+            format!("exit code {} doesn't fit in 32 bits", code),
+        ),
+    }
+}
 
+fn gc_collect(interp: &mut Interpreter, _args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(stats_object(interp, gc::collect()))
+}
+
+fn gc_stats(interp: &mut Interpreter, _args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(stats_object(interp, gc::stats()))
+}
+
+fn stats_object(interp: &Interpreter, stats: gc::Stats) -> Value {
+    let properties = [
+        ("collected", stats.collected),
+        ("objects", stats.objects),
+        ("lists", stats.lists),
+        ("functions", stats.functions),
+        ("cells", stats.cells),
+        ("collections", stats.collections),
+    ]
+    .into_iter()
+    .map(|(name, count)| (Ptr::new(name.to_string()), Value::Integer(count as i64)))
+    .collect();
+
+    Value::new_object(Object::new(
+        interp.builtins().types.object.clone(),
+        properties,
+    ))
+}
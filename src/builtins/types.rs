@@ -1,7 +1,10 @@
+use std::{collections::HashMap, rc::Rc};
+
 use crate::{
+    error::{ErrorKind, RuntimeError},
     get_native_prop,
-    ptr::{Ptr, PtrMut},
-    value::{ArgPattern, Type, Value},
+    ptr::Ptr,
+    value::{ArgPattern, Object, Type, Value},
     Interpreter,
 };
 
@@ -18,6 +21,10 @@ pub struct BuiltinTypes {
     pub object: Ptr<Type>,
     pub null: Ptr<Type>,
     pub ty: Ptr<Type>,
+    pub stop: Ptr<Type>,
+    pub error: Ptr<Type>,
+    pub list_iterator: Ptr<Type>,
+    pub string_iterator: Ptr<Type>,
 }
 
 impl Default for BuiltinTypes {
@@ -42,6 +49,10 @@ impl BuiltinTypes {
             function: function_ty(object_ty.clone()),
             code: code_ty(object_ty.clone()),
             null: null_ty(object_ty.clone()),
+            stop: stop_ty(object_ty.clone()),
+            error: error_ty(object_ty.clone()),
+            list_iterator: list_iterator_ty(object_ty.clone()),
+            string_iterator: string_iterator_ty(object_ty.clone()),
             ty: ty_ty(object_ty),
         }
     }
@@ -51,21 +62,57 @@ fn string_ty(base: Ptr<Type>) -> Ptr<Type> {
     let mut ty = Type::new(Ptr::new("str".to_string()), base);
 
     ty.display = Some(Value::Native(
-        |_, args| args[0].clone(),
+        |_, args| Ok(args[0].clone()),
         ArgPattern::Exact(1),
     ));
 
     ty.add = Some(Value::Native(
         |i, args| {
-            Value::String(Ptr::new(format!(
+            Ok(Value::String(Ptr::new(format!(
                 "{}{}",
-                args[0].string(i).value(),
-                args[1].string(i).value()
-            )))
+                args[0].string(i)?.value(),
+                args[1].string(i)?.value()
+            ))))
         },
         ArgPattern::Exact(2),
     ));
 
+    ty.iter = Some(Value::Native(
+        |i, args| {
+            let ty = i.builtins().types.string_iterator.clone();
+            Ok(new_iterator(ty, args[0].clone()))
+        },
+        ArgPattern::Exact(1),
+    ));
+
+    Ptr::new(ty)
+}
+
+fn string_iterator_ty(base: Ptr<Type>) -> Ptr<Type> {
+    let mut ty = Type::new(Ptr::new("str_iterator".to_string()), base);
+
+    ty.iter = Some(Value::Native(
+        |_, args| Ok(args[0].clone()),
+        ArgPattern::Exact(1),
+    ));
+
+    ty.next = Some(Value::Native(
+        |i, args| {
+            iterator_next(i, &args[0], |string, index| {
+                let string = string.string(i)?;
+                let next = string.value()[index..].chars().next().map(|chr| {
+                    (
+                        Value::String(Ptr::new(chr.to_string())),
+                        index + chr.len_utf8(),
+                    )
+                });
+
+                Ok(next)
+            })
+        },
+        ArgPattern::Exact(1),
+    ));
+
     Ptr::new(ty)
 }
 
@@ -76,7 +123,7 @@ fn list_ty(base: Ptr<Type>) -> Ptr<Type> {
         |interp, args| {
             let mut string = "[".to_string();
 
-            let list = args[0].list(interp).value().to_vec();
+            let list = args[0].list(interp)?.value().to_vec();
 
             for i in 0..list.len() {
                 if i > 0 {
@@ -86,59 +133,72 @@ fn list_ty(base: Ptr<Type>) -> Ptr<Type> {
                 let display = get_native_prop!(interp, list[i], display);
                 string.push_str(
                     interp
-                        .call_with_return(display, &list[i..i + 1])
-                        .string(interp)
+                        .call_with_return(display, &list[i..i + 1])?
+                        .string(interp)?
                         .value(),
                 );
             }
 
             string.push(']');
 
-            Value::String(Ptr::new(string))
+            Ok(Value::String(Ptr::new(string)))
         },
         ArgPattern::Exact(1),
     ));
 
     ty.get_subscript = Some(Value::Native(
         |i, args| {
-            args[0]
-                .list(i)
-                .value()
-                .get(args[1].as_int(i) as usize)
-                .expect("index out of bounds")
-                .clone()
+            let list = args[0].list(i)?;
+            let index = args[1].as_int(i)?;
+            let list = list.value();
+
+            match list_index(&list, index) {
+                Some(index) => Ok(list[index].clone()),
+                None => Err(index_error(index, list.len())),
+            }
         },
         ArgPattern::Exact(2),
     ));
 
     ty.set_subscript = Some(Value::Native(
         |i, args| {
-            *args[0]
-                .list(i)
-                .value_mut()
-                .get_mut(args[1].as_int(i) as usize)
-                .expect("index out of bounds") = args[2].clone();
+            let list = args[0].list(i)?;
+            let index = args[1].as_int(i)?;
+            let mut list = list.value_mut();
+
+            match list_index(&list, index) {
+                Some(index) => list[index] = args[2].clone(),
+                None => return Err(index_error(index, list.len())),
+            }
 
-            Value::Null
+            Ok(Value::Null)
         },
         ArgPattern::Exact(3),
     ));
 
     ty.add = Some(Value::Native(
         |i, args| {
-            let mut new_list = args[0].list(i).value().clone();
-            new_list.extend_from_slice(&*args[1].list(i).value());
-            Value::List(PtrMut::new(new_list))
+            let mut new_list = args[0].list(i)?.value().clone();
+            new_list.extend_from_slice(&args[1].list(i)?.value());
+            Ok(Value::new_list(new_list))
         },
         ArgPattern::Exact(2),
     ));
 
+    ty.iter = Some(Value::Native(
+        |i, args| {
+            let ty = i.builtins().types.list_iterator.clone();
+            Ok(new_iterator(ty, args[0].clone()))
+        },
+        ArgPattern::Exact(1),
+    ));
+
     ty.properties.insert(
         Ptr::new("push".to_string()),
         Value::Native(
             |i, args| {
-                args[0].list(i).value_mut().push(args[1].clone());
-                Value::Null
+                args[0].list(i)?.value_mut().push(args[1].clone());
+                Ok(Value::Null)
             },
             ArgPattern::Exact(2),
         ),
@@ -147,102 +207,237 @@ fn list_ty(base: Ptr<Type>) -> Ptr<Type> {
     Ptr::new(ty)
 }
 
+fn list_iterator_ty(base: Ptr<Type>) -> Ptr<Type> {
+    let mut ty = Type::new(Ptr::new("list_iterator".to_string()), base);
+
+    ty.iter = Some(Value::Native(
+        |_, args| Ok(args[0].clone()),
+        ArgPattern::Exact(1),
+    ));
+
+    ty.next = Some(Value::Native(
+        |i, args| {
+            iterator_next(i, &args[0], |list, index| {
+                let item = list.list(i)?.value().get(index).cloned();
+                Ok(item.map(|item| (item, index + 1)))
+            })
+        },
+        ArgPattern::Exact(1),
+    ));
+
+    Ptr::new(ty)
+}
+
+/// Convert a script index into a list index, `None` if it is out of bounds.
+fn list_index(list: &[Value], index: i64) -> Option<usize> {
+    usize::try_from(index)
+        .ok()
+        .filter(|index| *index < list.len())
+}
+
+fn index_error(index: i64, len: usize) -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::Index,
+        format!("index {} out of bounds for list of length {}", index, len),
+    )
+}
+
+/// Create a native iterator of type `ty` over `target`.
+fn new_iterator(ty: Ptr<Type>, target: Value) -> Value {
+    let mut properties = HashMap::new();
+    properties.insert(Ptr::new("target".to_string()), target);
+    properties.insert(Ptr::new("index".to_string()), Value::Integer(0));
+
+    Value::new_object(Object::new(ty, properties))
+}
+
+/// Advance a native iterator, `next` receives the iterated value and the
+/// current index and returns the next item and index or `None` when exhausted.
+fn iterator_next(
+    interp: &Interpreter,
+    iterator: &Value,
+    next: impl FnOnce(&Value, usize) -> Result<Option<(Value, usize)>, RuntimeError>,
+) -> Result<Value, RuntimeError> {
+    let iterator = iterator.object(interp)?;
+    let target_key = Ptr::new("target".to_string());
+    let index_key = Ptr::new("index".to_string());
+
+    let target = iterator
+        .value()
+        .get_property(&target_key)
+        .unwrap_or(Value::Null);
+    let index = iterator
+        .value()
+        .get_property(&index_key)
+        .unwrap_or(Value::Null)
+        .int(interp)?;
+
+    match next(&target, index as usize)? {
+        Some((item, index)) => {
+            iterator
+                .value_mut()
+                .set_property(index_key, Value::Integer(index as i64));
+            Ok(item)
+        }
+        None => Ok(interp.builtins().stop.clone()),
+    }
+}
+
 fn integer_ty(base: Ptr<Type>) -> Ptr<Type> {
     let mut ty = Type::new(Ptr::new("int".to_string()), base);
 
     ty.display = Some(Value::Native(
-        |i, args| Value::String(Ptr::new(format!("{}", args[0].int(i)))),
+        |i, args| Ok(Value::String(Ptr::new(format!("{}", args[0].int(i)?)))),
         ArgPattern::Exact(1),
     ));
 
     ty.add = Some(Value::Native(
-        |i, args| Value::Integer(args[0].int(i) + args[1].as_int(i)),
+        |i, args| {
+            let result = args[0].int(i)?.checked_add(args[1].as_int(i)?);
+            checked_result(result, IntOperation::Add)
+        },
         ArgPattern::Exact(2),
     ));
 
     ty.subtract = Some(Value::Native(
-        |i, args| Value::Integer(args[0].int(i) - args[1].as_int(i)),
+        |i, args| {
+            let result = args[0].int(i)?.checked_sub(args[1].as_int(i)?);
+            checked_result(result, IntOperation::Subtract)
+        },
         ArgPattern::Exact(2),
     ));
 
     ty.divide = Some(Value::Native(
-        |i, args| Value::Integer(args[0].int(i) / args[1].as_int(i)),
+        |i, args| {
+            let (lhs, rhs) = (args[0].int(i)?, args[1].as_int(i)?);
+            if rhs == 0 {
+                return Err(RuntimeError::new(ErrorKind::Arithmetic, "division by zero"));
+            }
+
+            checked_result(lhs.checked_div(rhs), IntOperation::Divide)
+        },
         ArgPattern::Exact(2),
     ));
 
     ty.multiply = Some(Value::Native(
-        |i, args| Value::Integer(args[0].int(i) * args[1].as_int(i)),
+        |i, args| {
+            let result = args[0].int(i)?.checked_mul(args[1].as_int(i)?);
+            checked_result(result, IntOperation::Multiply)
+        },
         ArgPattern::Exact(2),
     ));
 
+    ty.negate = Some(Value::Native(
+        |i, args| checked_result(args[0].int(i)?.checked_neg(), IntOperation::Negate),
+        ArgPattern::Exact(1),
+    ));
+
     ty.less_than = Some(Value::Native(
-        |i, args| Value::Bool(args[0].int(i) < args[1].as_int(i)),
+        |i, args| Ok(Value::Bool(args[0].int(i)? < args[1].as_int(i)?)),
         ArgPattern::Exact(2),
     ));
 
     ty.greater_than = Some(Value::Native(
-        |i, args| Value::Bool(args[0].int(i) > args[1].as_int(i)),
+        |i, args| Ok(Value::Bool(args[0].int(i)? > args[1].as_int(i)?)),
         ArgPattern::Exact(2),
     ));
 
     ty.less_than_or_equal = Some(Value::Native(
-        |i, args| Value::Bool(args[0].int(i) <= args[1].as_int(i)),
+        |i, args| Ok(Value::Bool(args[0].int(i)? <= args[1].as_int(i)?)),
         ArgPattern::Exact(2),
     ));
 
     ty.greater_than_or_equal = Some(Value::Native(
-        |i, args| Value::Bool(args[0].int(i) >= args[1].as_int(i)),
+        |i, args| Ok(Value::Bool(args[0].int(i)? >= args[1].as_int(i)?)),
         ArgPattern::Exact(2),
     ));
 
     Ptr::new(ty)
 }
 
+/// An integer operation that fails when its result overflows.
+#[derive(Debug, Clone, Copy)]
+enum IntOperation {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Negate,
+}
+
+impl IntOperation {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Add => "add",
+            Self::Subtract => "subtract",
+            Self::Multiply => "multiply",
+            Self::Divide => "divide",
+            Self::Negate => "negate",
+        }
+    }
+}
+
+/// Turn the result of a checked integer operation into a value, `None`
+/// means the result overflowed.
+fn checked_result(result: Option<i64>, operation: IntOperation) -> Result<Value, RuntimeError> {
+    result.map(Value::Integer).ok_or_else(|| {
+        RuntimeError::new(
+            ErrorKind::Arithmetic,
+            format!("integer overflow in {}", operation.name()),
+        )
+    })
+}
+
 fn float_ty(base: Ptr<Type>) -> Ptr<Type> {
     let mut ty = Type::new(Ptr::new("float".to_string()), base);
 
     ty.display = Some(Value::Native(
-        |i, args| Value::String(Ptr::new(format!("{}", args[0].float(i)))),
+        |i, args| Ok(Value::String(Ptr::new(format!("{}", args[0].float(i)?)))),
         ArgPattern::Exact(1),
     ));
 
     ty.add = Some(Value::Native(
-        |i, args| Value::Float(args[0].float(i) + args[1].as_float(i)),
+        |i, args| Ok(Value::Float(args[0].float(i)? + args[1].as_float(i)?)),
         ArgPattern::Exact(2),
     ));
 
     ty.subtract = Some(Value::Native(
-        |i, args| Value::Float(args[0].float(i) - args[1].as_float(i)),
+        |i, args| Ok(Value::Float(args[0].float(i)? - args[1].as_float(i)?)),
         ArgPattern::Exact(2),
     ));
 
     ty.divide = Some(Value::Native(
-        |i, args| Value::Float(args[0].float(i) / args[1].as_float(i)),
+        |i, args| Ok(Value::Float(args[0].float(i)? / args[1].as_float(i)?)),
         ArgPattern::Exact(2),
     ));
 
     ty.multiply = Some(Value::Native(
-        |i, args| Value::Float(args[0].float(i) * args[1].as_float(i)),
+        |i, args| Ok(Value::Float(args[0].float(i)? * args[1].as_float(i)?)),
         ArgPattern::Exact(2),
     ));
 
+    ty.negate = Some(Value::Native(
+        |i, args| Ok(Value::Float(-args[0].float(i)?)),
+        ArgPattern::Exact(1),
+    ));
+
     ty.less_than = Some(Value::Native(
-        |i, args| Value::Bool(args[0].float(i) < args[1].as_float(i)),
+        |i, args| Ok(Value::Bool(args[0].float(i)? < args[1].as_float(i)?)),
         ArgPattern::Exact(2),
     ));
 
     ty.greater_than = Some(Value::Native(
-        |i, args| Value::Bool(args[0].float(i) > args[1].as_float(i)),
+        |i, args| Ok(Value::Bool(args[0].float(i)? > args[1].as_float(i)?)),
         ArgPattern::Exact(2),
     ));
 
     ty.less_than_or_equal = Some(Value::Native(
-        |i, args| Value::Bool(args[0].float(i) <= args[1].as_float(i)),
+        |i, args| Ok(Value::Bool(args[0].float(i)? <= args[1].as_float(i)?)),
         ArgPattern::Exact(2),
     ));
 
     ty.greater_than_or_equal = Some(Value::Native(
-        |i, args| Value::Bool(args[0].float(i) >= args[1].as_float(i)),
+        |i, args| Ok(Value::Bool(args[0].float(i)? >= args[1].as_float(i)?)),
         ArgPattern::Exact(2),
     ));
 
@@ -253,7 +448,7 @@ fn function_ty(base: Ptr<Type>) -> Ptr<Type> {
     let mut ty = Type::new(Ptr::new("function".to_string()), base);
 
     ty.display = Some(Value::Native(
-        |_i, _args| Value::String(Ptr::new("<function object>".to_string())),
+        |_i, _args| Ok(Value::String(Ptr::new("<function object>".to_string()))),
         ArgPattern::Exact(1),
     ));
 
@@ -264,7 +459,7 @@ fn bool_ty(base: Ptr<Type>) -> Ptr<Type> {
     let mut ty = Type::new(Ptr::new("bool".to_string()), base);
 
     ty.display = Some(Value::Native(
-        |i, args| Value::String(Ptr::new(format!("{}", args[0].bool(i)))),
+        |i, args| Ok(Value::String(Ptr::new(format!("{}", args[0].bool(i)?)))),
         ArgPattern::Exact(1),
     ));
 
@@ -275,7 +470,7 @@ fn frame_ty(base: Ptr<Type>) -> Ptr<Type> {
     let mut ty = Type::new(Ptr::new("Frame".to_string()), base);
 
     ty.display = Some(Value::Native(
-        |_i, _args| Value::String(Ptr::new("<frame object>".to_string())),
+        |_i, _args| Ok(Value::String(Ptr::new("<frame object>".to_string()))),
         ArgPattern::Exact(1),
     ));
 
@@ -286,7 +481,7 @@ fn native_ty(base: Ptr<Type>) -> Ptr<Type> {
     let mut ty = Type::new(Ptr::new("NativeFunction".to_string()), base);
 
     ty.display = Some(Value::Native(
-        |_i, _args| Value::String(Ptr::new("<native function>".to_string())),
+        |_i, _args| Ok(Value::String(Ptr::new("<native function>".to_string()))),
         ArgPattern::Exact(1),
     ));
 
@@ -297,7 +492,7 @@ fn code_ty(base: Ptr<Type>) -> Ptr<Type> {
     let mut ty = Type::new(Ptr::new("Code".to_string()), base);
 
     ty.display = Some(Value::Native(
-        |_i, _args| Value::String(Ptr::new("<code object>".to_string())),
+        |_i, _args| Ok(Value::String(Ptr::new("<code object>".to_string()))),
         ArgPattern::Exact(1),
     ));
 
@@ -308,7 +503,38 @@ fn null_ty(base: Ptr<Type>) -> Ptr<Type> {
     let mut ty = Type::new(Ptr::new("null".to_string()), base);
 
     ty.display = Some(Value::Native(
-        |_i, _args| Value::String(Ptr::new("null".to_string())),
+        |_i, _args| Ok(Value::String(Ptr::new("null".to_string()))),
+        ArgPattern::Exact(1),
+    ));
+
+    Ptr::new(ty)
+}
+
+fn stop_ty(base: Ptr<Type>) -> Ptr<Type> {
+    let mut ty = Type::new(Ptr::new("stop".to_string()), base);
+
+    ty.display = Some(Value::Native(
+        |_i, _args| Ok(Value::String(Ptr::new("stop".to_string()))),
+        ArgPattern::Exact(1),
+    ));
+
+    Ptr::new(ty)
+}
+
+fn error_ty(base: Ptr<Type>) -> Ptr<Type> {
+    let mut ty = Type::new(Ptr::new("error".to_string()), base);
+
+    ty.display = Some(Value::Native(
+        |i, args| {
+            let kind = get_property(i, &args[0], &Ptr::new("type".to_string()))?;
+            let message = get_property(i, &args[0], &Ptr::new("message".to_string()))?;
+
+            Ok(Value::String(Ptr::new(format!(
+                "{}: {}",
+                kind.string(i)?.value(),
+                message.string(i)?.value()
+            ))))
+        },
         ArgPattern::Exact(1),
     ));
 
@@ -319,54 +545,90 @@ fn object_ty() -> Ptr<Type> {
     let mut ty = Type::root(Ptr::new("object".to_string()));
 
     ty.display = Some(Value::Native(
-        |_i, _args| Value::String(Ptr::new("<object>".to_string())),
+        |_i, _args| Ok(Value::String(Ptr::new("<object>".to_string()))),
         ArgPattern::Exact(1),
     ));
 
     ty.equals = Some(Value::Native(
-        |_i, args| Value::Bool(equals(&args[0], &args[1])),
+        |_i, args| Ok(Value::Bool(equals(&args[0], &args[1]))),
         ArgPattern::Exact(2),
     ));
 
     ty.not_equals = Some(Value::Native(
-        |_i, args| Value::Bool(!equals(&args[0], &args[1])),
+        |_i, args| Ok(Value::Bool(!equals(&args[0], &args[1]))),
         ArgPattern::Exact(2),
     ));
 
+    ty.not = Some(Value::Native(
+        |_i, args| Ok(Value::Bool(!args[0].as_bool())),
+        ArgPattern::Exact(1),
+    ));
+
     ty.get_property = Some(Value::Native(
-        |i, args| get_property(i, &args[0], &args[1].string(i)),
+        |i, args| get_property(i, &args[0], &args[1].string(i)?),
         ArgPattern::Exact(2),
     ));
 
     ty.set_property = Some(Value::Native(
         |i, args| {
-            set_property(i, &args[0], args[1].string(i), args[2].clone());
-            Value::Null
+            set_property(i, &args[0], args[1].string(i)?, args[2].clone())?;
+            Ok(Value::Null)
         },
         ArgPattern::Exact(3),
     ));
 
+    // Objects take part in iteration by defining `iter` and `next` methods
+    ty.iter = Some(Value::Native(
+        |i, args| {
+            let iter = iterator_method(i, &args[0], "iter")?;
+            i.call_with_return(iter, &[])
+        },
+        ArgPattern::Exact(1),
+    ));
+
+    ty.next = Some(Value::Native(
+        |i, args| {
+            let next = iterator_method(i, &args[0], "next")?;
+            i.call_with_return(next, &[])
+        },
+        ArgPattern::Exact(1),
+    ));
+
     Ptr::new(ty)
 }
 
-fn set_property(interp: &Interpreter, target: &Value, prop: Ptr<String>, value: Value) {
+fn set_property(
+    interp: &Interpreter,
+    target: &Value,
+    prop: Ptr<String>,
+    value: Value,
+) -> Result<(), RuntimeError> {
     if let Value::Object(obj) = target {
         obj.value_mut().set_property(prop, value);
+        Ok(())
     } else {
-        panic!(
-            "object of type `{}` does not support `$set_property`",
-            target.ty(interp.builtins()).value().name.value()
-        );
+        Err(RuntimeError::new(
+            ErrorKind::Type,
+            format!(
+                "object of type `{}` does not support `$set_property`",
+                target.ty(interp.builtins()).value().name.value()
+            ),
+        ))
     }
 }
 
-fn get_property(interp: &Interpreter, target: &Value, prop: &Ptr<String>) -> Value {
+/// Look up `prop` on `target`, functions are bound to `target`.
+pub(crate) fn get_property(
+    interp: &Interpreter,
+    target: &Value,
+    prop: &Ptr<String>,
+) -> Result<Value, RuntimeError> {
     if let Value::Object(obj) = target {
         match obj.value().get_property(prop) {
-            Some(res @ Value::Function(_)) | Some(res @ Value::Native(..)) => {
-                return Value::Bound(Ptr::new(res), Ptr::new(target.clone()));
+            Some(res @ (Value::Function(_) | Value::Native(..) | Value::NativeClosure(..))) => {
+                return Ok(Value::Bound(Ptr::new(res), Ptr::new(target.clone())));
             }
-            Some(value) => return value,
+            Some(value) => return Ok(value),
             _ => {}
         }
     }
@@ -375,21 +637,50 @@ fn get_property(interp: &Interpreter, target: &Value, prop: &Ptr<String>) -> Val
 
     loop {
         match ty.value().properties.get(prop) {
-            Some(res @ Value::Function(_)) | Some(res @ Value::Native(..)) => {
-                return Value::Bound(Ptr::new(res.clone()), Ptr::new(target.clone()));
+            Some(res @ (Value::Function(_) | Value::Native(..) | Value::NativeClosure(..))) => {
+                return Ok(Value::Bound(
+                    Ptr::new(res.clone()),
+                    Ptr::new(target.clone()),
+                ));
             }
-            Some(value) => return value.clone(),
+            Some(value) => return Ok(value.clone()),
             _ => {}
         }
 
         if let Some(base) = ty.value().base.clone() {
             ty = base;
         } else {
-            panic!("object has no property `{}`", prop.value());
+            return Err(RuntimeError::new(
+                ErrorKind::Property,
+                format!("object has no property `{}`", prop.value()),
+            ));
         }
     }
 }
 
+/// Look up the `iter` or `next` method of an object taking part in
+/// iteration. A value without it is not iterable, which is a type error.
+pub(crate) fn iterator_method(
+    interp: &Interpreter,
+    target: &Value,
+    name: &str,
+) -> Result<Value, RuntimeError> {
+    get_property(interp, target, &Ptr::new(name.to_string())).map_err(|err| {
+        if err.kind != ErrorKind::Property {
+            return err;
+        }
+
+        let ty = target.ty(interp.builtins());
+        let ty = ty.value().name.value();
+        let message = match name {
+            "iter" => format!("`{}` is not iterable", ty),
+            _ => format!("`{}` is not an iterator", ty),
+        };
+
+        RuntimeError::new(ErrorKind::Type, message)
+    })
+}
+
 fn equals(lhs: &Value, rhs: &Value) -> bool {
     match (lhs, rhs) {
         (Value::Bool(l), Value::Bool(r)) => l == r,
@@ -406,6 +697,8 @@ fn equals(lhs: &Value, rhs: &Value) -> bool {
         (Value::Object(l), Value::Object(r)) => l.id() == r.id(),
         (Value::Null, Value::Null) => true,
         (Value::Native(l, _), Value::Native(r, _)) => *l as usize == *r as usize,
+        (Value::NativeClosure(l, _), Value::NativeClosure(r, _)) => Rc::ptr_eq(l, r),
+        (Value::Host(l, _), Value::Host(r, _)) => Rc::ptr_eq(l, r),
         _ => false,
     }
 }
@@ -414,9 +707,46 @@ fn ty_ty(base: Ptr<Type>) -> Ptr<Type> {
     let mut ty = Type::new(Ptr::new("object".to_string()), base);
 
     ty.display = Some(Value::Native(
-        |_i, _args| Value::String(Ptr::new("<type object>".to_string())),
+        |_i, _args| Ok(Value::String(Ptr::new("<type object>".to_string()))),
         ArgPattern::Exact(1),
     ));
 
     Ptr::new(ty)
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{value::Value, Engine};
+
+    fn error_message(source: &str) -> String {
+        let source = format!(
+            "let m = null; try {{ {} }} catch e {{ m = e.message; }} m",
+            source
+        );
+        match Engine::new().eval(&source).expect("script runs") {
+            Value::String(message) => message.value().clone(),
+            _ => panic!("script didn't raise an error"),
+        }
+    }
+
+    #[test]
+    fn integer_division() {
+        assert_eq!(error_message("1 / 0;"), "division by zero");
+        assert_eq!(
+            error_message("(0 - 9223372036854775807 - 1) / -1;"),
+            "integer overflow in divide"
+        );
+    }
+
+    #[test]
+    fn integer_overflow() {
+        assert_eq!(
+            error_message("9223372036854775807 + 1;"),
+            "integer overflow in add"
+        );
+        assert_eq!(
+            error_message("let x = 0 - 9223372036854775807 - 1; -x;"),
+            "integer overflow in negate"
+        );
+    }
+}
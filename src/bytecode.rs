@@ -0,0 +1,613 @@
+use std::fmt;
+
+use crate::{
+    frame::{Code, DebugInfo, Handler, Variables},
+    instruction::Instruction,
+    ptr::Ptr,
+    value::Value,
+};
+
+/// First bytes of every serialized bytecode file.
+pub const MAGIC: [u8; 4] = *b"SANC";
+/// Version of the format, bumped whenever the encoding changes.
+pub const VERSION: u16 = 1;
+
+/// Code objects nested deeper than this are rejected, so a corrupt file
+/// can't overflow the stack of the loader.
+const MAX_NESTING: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BytecodeError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    UnexpectedEof,
+    InvalidTag {
+        what: &'static str,
+        tag: u8,
+    },
+    InvalidUtf8,
+    TrailingBytes,
+    TooDeep,
+    /// The file decoded, but describes code that can't run.
+    Invalid(String),
+    /// A constant of this type can't be serialized.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a san-script bytecode file"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported bytecode version {}, expected {}",
+                version, VERSION
+            ),
+            Self::UnexpectedEof => write!(f, "unexpected end of bytecode"),
+            Self::InvalidTag { what, tag } => write!(f, "invalid {} tag {}", what, tag),
+            Self::InvalidUtf8 => write!(f, "string is not valid utf-8"),
+            Self::TrailingBytes => write!(f, "trailing bytes after code"),
+            Self::TooDeep => write!(f, "code objects are nested too deeply"),
+            Self::Invalid(message) => write!(f, "invalid code: {}", message),
+            Self::Unsupported(ty) => write!(f, "can't serialize constant of type {}", ty),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+/// Serialize `code` and the code objects in its constants.
+pub fn serialize(code: &Code) -> Result<Vec<u8>, BytecodeError> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    write_code(&mut out, code)?;
+    Ok(out)
+}
+
+/// Load code serialized by `serialize`, the result is verified so it
+/// can't make the interpreter panic.
+pub fn deserialize(bytes: &[u8]) -> Result<Code, BytecodeError> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(BytecodeError::BadMagic);
+    }
+
+    let version = u16::from_le_bytes([reader.u8()?, reader.u8()?]);
+    if version != VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+
+    let code = read_code(&mut reader, 0)?;
+
+    // The top-level code runs in a root frame, which has no function to
+    // receive captured cells from.
+    if !code.captures().is_empty() {
+        return Err(BytecodeError::Invalid(
+            "top-level code captures cells".to_string(),
+        ));
+    }
+
+    if reader.position != bytes.len() {
+        return Err(BytecodeError::TrailingBytes);
+    }
+
+    Ok(code)
+}
+
+/// Whether `bytes` start like a serialized bytecode file.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+macro_rules! instruction_tags {
+    ($($tag:literal => $name:ident $(($operand:ident))?,)*) => {
+        fn write_instruction(out: &mut Vec<u8>, instruction: Instruction) {
+            match instruction {
+                $(Instruction::$name $(($operand))? => {
+                    out.push($tag);
+                    $(write_usize(out, $operand);)?
+                })*
+            }
+        }
+
+        fn read_instruction(reader: &mut Reader) -> Result<Instruction, BytecodeError> {
+            match reader.u8()? {
+                $($tag => Ok(Instruction::$name $(({
+                    let $operand = reader.usize()?;
+                    $operand
+                }))?),)*
+                tag => Err(BytecodeError::InvalidTag { what: "instruction", tag }),
+            }
+        }
+    };
+}
+
+instruction_tags! {
+    0 => Call(argc),
+    1 => JumpFalse(target),
+    2 => JumpFalseOrPop(target),
+    3 => JumpTrueOrPop(target),
+    4 => ForIter(target),
+    5 => GetIter,
+    6 => Jump(target),
+    7 => Return,
+    8 => Throw,
+    9 => CreateFunction,
+    10 => CreateList(len),
+    11 => CreateObject(len),
+    12 => Pop,
+    13 => Echo,
+    14 => LoadConstant(consi),
+    15 => Declare(namei),
+    16 => StoreProperty(namei),
+    17 => LoadProperty(namei),
+    18 => Exit(code),
+    19 => StoreSubscript,
+    20 => LoadSubscript,
+    21 => Add,
+    22 => Subtract,
+    23 => Multiply,
+    24 => Divide,
+    25 => Equals,
+    26 => NotEquals,
+    27 => LessThan,
+    28 => GreaterThan,
+    29 => LessThanOrEqual,
+    30 => GreaterThanOrEqual,
+    31 => Negate,
+    32 => Not,
+    33 => LoadLocal(slot),
+    34 => StoreLocal(slot),
+    35 => LoadGlobal(namei),
+    36 => StoreGlobal(namei),
+    37 => LoadCell(index),
+    38 => StoreCell(index),
+}
+
+const NULL: u8 = 0;
+const BOOL: u8 = 1;
+const INTEGER: u8 = 2;
+const FLOAT: u8 = 3;
+const STRING: u8 = 4;
+const CODE: u8 = 5;
+
+fn write_code(out: &mut Vec<u8>, code: &Code) -> Result<(), BytecodeError> {
+    write_str(out, code.name());
+    write_str(out, code.file());
+    write_usize(out, code.parameters());
+
+    write_usize(out, code.instructions().len());
+    for instruction in code.instructions() {
+        write_instruction(out, *instruction);
+    }
+
+    write_usize(out, code.constants().len());
+    for constant in code.constants() {
+        match constant {
+            Value::Null => out.push(NULL),
+            Value::Bool(b) => out.extend_from_slice(&[BOOL, *b as u8]),
+            Value::Integer(int) => {
+                out.push(INTEGER);
+                out.extend_from_slice(&int.to_le_bytes());
+            }
+            Value::Float(float) => {
+                out.push(FLOAT);
+                out.extend_from_slice(&float.to_le_bytes());
+            }
+            Value::String(str) => {
+                out.push(STRING);
+                write_str(out, str.value());
+            }
+            Value::Code(code) => {
+                out.push(CODE);
+                write_code(out, code.value())?;
+            }
+            Value::Object(_) => return Err(BytecodeError::Unsupported("object")),
+            Value::List(_) => return Err(BytecodeError::Unsupported("list")),
+            Value::Frame(_) => return Err(BytecodeError::Unsupported("frame")),
+            Value::Function(_) => return Err(BytecodeError::Unsupported("function")),
+            Value::Bound(..) => return Err(BytecodeError::Unsupported("bound")),
+            Value::Native(..) | Value::NativeClosure(..) => {
+                return Err(BytecodeError::Unsupported("native"))
+            }
+            Value::Type(_) => return Err(BytecodeError::Unsupported("type")),
+            Value::Host(..) => return Err(BytecodeError::Unsupported("host")),
+        }
+    }
+
+    write_usize(out, code.names().len());
+    for name in code.names() {
+        write_str(out, name.value());
+    }
+
+    write_usize(out, code.locals().len());
+    for local in code.locals() {
+        write_str(out, local.value());
+    }
+
+    write_usize(out, code.cells().len());
+    for cell in code.cells() {
+        write_str(out, cell.value());
+    }
+
+    write_usize(out, code.captures().len());
+    for index in code.captures() {
+        write_usize(out, *index);
+    }
+
+    write_usize(out, code.handlers().len());
+    for handler in code.handlers() {
+        write_usize(out, handler.start);
+        write_usize(out, handler.end);
+        write_usize(out, handler.target);
+        write_usize(out, handler.depth);
+    }
+
+    write_usize(out, code.lines().len());
+    for (start, line) in code.lines() {
+        write_usize(out, *start);
+        write_usize(out, *line);
+    }
+
+    Ok(())
+}
+
+fn read_code(reader: &mut Reader, nesting: usize) -> Result<Code, BytecodeError> {
+    if nesting > MAX_NESTING {
+        return Err(BytecodeError::TooDeep);
+    }
+
+    let name = reader.string()?;
+    let file = reader.string()?;
+    let parameters = reader.usize()?;
+
+    let mut instructions = Vec::new();
+    for _ in 0..reader.usize()? {
+        instructions.push(read_instruction(reader)?);
+    }
+
+    let mut constants = Vec::new();
+    for _ in 0..reader.usize()? {
+        let constant = match reader.u8()? {
+            NULL => Value::Null,
+            BOOL => match reader.u8()? {
+                0 => Value::Bool(false),
+                1 => Value::Bool(true),
+                tag => return Err(BytecodeError::InvalidTag { what: "bool", tag }),
+            },
+            INTEGER => Value::Integer(i64::from_le_bytes(reader.array()?)),
+            FLOAT => Value::Float(f64::from_le_bytes(reader.array()?)),
+            STRING => Value::String(Ptr::new(reader.string()?)),
+            CODE => Value::Code(Ptr::new(read_code(reader, nesting + 1)?)),
+            tag => {
+                return Err(BytecodeError::InvalidTag {
+                    what: "constant",
+                    tag,
+                })
+            }
+        };
+        constants.push(constant);
+    }
+
+    let mut names = Vec::new();
+    for _ in 0..reader.usize()? {
+        names.push(Ptr::new(reader.string()?));
+    }
+
+    let mut locals = Vec::new();
+    for _ in 0..reader.usize()? {
+        locals.push(Ptr::new(reader.string()?));
+    }
+
+    let mut cells = Vec::new();
+    for _ in 0..reader.usize()? {
+        cells.push(Ptr::new(reader.string()?));
+    }
+
+    let mut captures = Vec::new();
+    for _ in 0..reader.usize()? {
+        captures.push(reader.usize()?);
+    }
+
+    let mut handlers = Vec::new();
+    for _ in 0..reader.usize()? {
+        handlers.push(Handler {
+            start: reader.usize()?,
+            end: reader.usize()?,
+            target: reader.usize()?,
+            depth: reader.usize()?,
+        });
+    }
+
+    let mut lines = Vec::new();
+    for _ in 0..reader.usize()? {
+        lines.push((reader.usize()?, reader.usize()?));
+    }
+
+    Code::new(
+        instructions,
+        constants,
+        names,
+        Variables {
+            locals,
+            parameters,
+            cells,
+            captures,
+        },
+        handlers,
+        DebugInfo { name, file, lines },
+    )
+    .map_err(|err| BytecodeError::Invalid(err.to_string()))
+}
+
+fn write_usize(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&(value as u64).to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, str: &str) {
+    write_usize(out, str.len());
+    out.extend_from_slice(str.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BytecodeError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(BytecodeError::UnexpectedEof)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], BytecodeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn usize(&mut self) -> Result<usize, BytecodeError> {
+        usize::try_from(u64::from_le_bytes(self.array()?))
+            .map_err(|_| BytecodeError::Invalid("size doesn't fit in memory".to_string()))
+    }
+
+    fn string(&mut self) -> Result<String, BytecodeError> {
+        let len = self.usize()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| BytecodeError::InvalidUtf8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        disassembler::disassemble, error::ErrorKind, parser::Parser, CodeBuilder, Engine,
+        EngineError,
+    };
+
+    const SOURCE: &str = r#"
+let greeting = "hello";
+let count = fn(items) {
+    let total = 0;
+    for item in items { total = total + 1; }
+    ^ total;
+};
+let safe = fn() {
+    try { throw error("oops"); } catch e { ^ e.message; } finally { print(greeting); }
+};
+print(count([1, 2.5, true, null]), safe());
+"#;
+
+    fn compile(source: &str) -> Code {
+        let module = Parser::new()
+            .parse("test.san", source)
+            .expect("valid syntax");
+        let mut code_builder = CodeBuilder::new(0);
+        code_builder
+            .compile_module(&module, source)
+            .expect("valid program");
+        code_builder.build()
+    }
+
+    /// A module returning `null`, which has no names, so the offset of
+    /// every field is known.
+    fn minimal() -> Vec<u8> {
+        let code = Code::new(
+            vec![Instruction::LoadConstant(0), Instruction::Return],
+            vec![Value::Null],
+            Vec::new(),
+            Variables::default(),
+            Vec::new(),
+            DebugInfo::default(),
+        )
+        .expect("valid code");
+        serialize(&code).expect("serializable")
+    }
+
+    /// Offset of the first instruction in `minimal`, after the header,
+    /// the empty name and file, the parameters and the instruction count.
+    const FIRST_INSTRUCTION: usize = 6 + 8 + 8 + 8 + 8;
+
+    #[test]
+    fn round_trip() {
+        let code = compile(SOURCE);
+        let bytes = serialize(&code).expect("serializable");
+        let loaded = deserialize(&bytes).expect("valid bytecode");
+
+        assert_eq!(disassemble(&loaded), disassemble(&code));
+        assert_eq!(loaded.max_stack(), code.max_stack());
+        assert_eq!(serialize(&loaded).expect("serializable"), bytes);
+    }
+
+    #[test]
+    fn rejects_truncated() {
+        let bytes = serialize(&compile(SOURCE)).expect("serializable");
+
+        for len in 0..bytes.len() {
+            assert_eq!(
+                deserialize(&bytes[..len]).err(),
+                Some(BytecodeError::UnexpectedEof),
+                "truncated to {} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut bytes = minimal();
+        bytes.push(0);
+
+        assert_eq!(
+            deserialize(&bytes).err(),
+            Some(BytecodeError::TrailingBytes)
+        );
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let mut bytes = minimal();
+        bytes[0] = b'X';
+        assert_eq!(deserialize(&bytes).err(), Some(BytecodeError::BadMagic));
+
+        let mut bytes = minimal();
+        bytes[4..6].copy_from_slice(&99u16.to_le_bytes());
+        assert_eq!(
+            deserialize(&bytes).err(),
+            Some(BytecodeError::UnsupportedVersion(99))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_tag() {
+        let mut bytes = minimal();
+        bytes[FIRST_INSTRUCTION] = 255;
+
+        assert_eq!(
+            deserialize(&bytes).err(),
+            Some(BytecodeError::InvalidTag {
+                what: "instruction",
+                tag: 255
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unverified_code() {
+        // `LoadConstant(1)`, but there is only one constant.
+        let mut bytes = minimal();
+        bytes[FIRST_INSTRUCTION + 1] = 1;
+
+        assert!(matches!(
+            deserialize(&bytes),
+            Err(BytecodeError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_corrupted_bytes_without_panicking() {
+        let bytes = serialize(&compile(SOURCE)).expect("serializable");
+
+        for index in 0..bytes.len() {
+            let mut corrupted = bytes.clone();
+            corrupted[index] ^= 0xff;
+            // Any result is fine, as long as loading doesn't panic.
+            let _ = deserialize(&corrupted);
+        }
+    }
+
+    #[test]
+    fn rejects_top_level_captures() {
+        let code = Code::new(
+            vec![Instruction::LoadCell(0), Instruction::Return],
+            Vec::new(),
+            Vec::new(),
+            Variables {
+                cells: vec![Ptr::new("x".to_string())],
+                captures: vec![0],
+                ..Variables::default()
+            },
+            Vec::new(),
+            DebugInfo::default(),
+        )
+        .expect("valid code");
+        let bytes = serialize(&code).expect("serializable");
+
+        assert_eq!(
+            deserialize(&bytes).err(),
+            Some(BytecodeError::Invalid(
+                "top-level code captures cells".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn captures_of_returned_code() {
+        // `make` has a cell and returns the code of a function capturing
+        // it, the module turns that code into a function but has no cells.
+        let variables = |cells: &[&str], captures| Variables {
+            cells: cells
+                .iter()
+                .map(|name| Ptr::new(name.to_string()))
+                .collect(),
+            captures,
+            ..Variables::default()
+        };
+        let code = |instructions, constants, variables| {
+            Code::new(
+                instructions,
+                constants,
+                Vec::new(),
+                variables,
+                Vec::new(),
+                DebugInfo::default(),
+            )
+            .expect("valid code")
+        };
+        let inner = code(
+            vec![Instruction::LoadCell(0), Instruction::Return],
+            Vec::new(),
+            variables(&["x"], vec![0]),
+        );
+        let make = code(
+            vec![Instruction::LoadConstant(0), Instruction::Return],
+            vec![Value::Code(Ptr::new(inner))],
+            variables(&["x"], Vec::new()),
+        );
+        let module = code(
+            vec![
+                Instruction::LoadConstant(0),
+                Instruction::CreateFunction,
+                Instruction::Call(0),
+                Instruction::CreateFunction,
+                Instruction::Return,
+            ],
+            vec![Value::Code(Ptr::new(make))],
+            Variables::default(),
+        );
+
+        let bytes = serialize(&module).expect("serializable");
+        let loaded = deserialize(&bytes).expect("valid bytecode");
+
+        match Engine::new().run(loaded) {
+            Err(EngineError::Runtime(err)) => {
+                assert_eq!(err.kind, ErrorKind::Name);
+                assert_eq!(err.message, "the current frame has no cell 0");
+            }
+            _ => panic!("expected a NameError"),
+        }
+    }
+}
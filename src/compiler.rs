@@ -1,25 +1,52 @@
+use std::rc::Rc;
+
+use self::optimizer::Folder;
+pub use self::optimizer::OptimizationLevel;
+use self::resolver::{FunctionScope, Resolution};
 use crate::{
-    ast::{AssignmentTarget, Expression, Module, Operator, Statement},
-    frame::Code,
+    ast::{
+        AssignmentTarget, Expression, ExpressionKind, Module, Operator, Span, Statement,
+        StatementKind, UnaryOperator,
+    },
+    diagnostic::Diagnostic,
+    frame::{Code, DebugInfo, Handler, Variables},
     instruction::{ConstantIndex, Instruction, NameIndex},
     ptr::Ptr,
+    source::LineIndex,
     value::Value,
 };
 
+mod optimizer;
+mod resolver;
+
 #[derive(Debug, PartialEq)]
 pub enum Constant {
     Null,
+    Bool(bool),
     Integer(i64),
     Float(f64),
     String(String),
-    Code(CodeBuilder),
+    Code(Box<CodeBuilder>),
+}
+
+impl Constant {
+    /// Whether two constants can share an entry in the constant table.
+    /// Floats are compared by their bits, so `-0.0` isn't merged with
+    /// `0.0` and a `NaN` matches itself.
+    fn is_same(&self, other: &Constant) -> bool {
+        match (self, other) {
+            (Constant::Float(lhs), Constant::Float(rhs)) => lhs.to_bits() == rhs.to_bits(),
+            _ => self == other,
+        }
+    }
 }
 
 impl From<Constant> for Value {
     fn from(c: Constant) -> Self {
         match c {
             Constant::Null => Value::Null,
-            Constant::Code(code) => Value::Code(Ptr::new(code.build())),
+            Constant::Bool(b) => Value::Bool(b),
+            Constant::Code(code) => Value::Code(Ptr::new((*code).build())),
             Constant::Float(flt) => Value::Float(flt),
             Constant::String(str) => Value::String(Ptr::new(str)),
             Constant::Integer(int) => Value::Integer(int),
@@ -27,6 +54,12 @@ impl From<Constant> for Value {
     }
 }
 
+impl From<bool> for Constant {
+    fn from(v: bool) -> Self {
+        Self::Bool(v)
+    }
+}
+
 impl From<i64> for Constant {
     fn from(v: i64) -> Self {
         Self::Integer(v)
@@ -47,7 +80,39 @@ impl From<String> for Constant {
 
 impl From<CodeBuilder> for Constant {
     fn from(v: CodeBuilder) -> Self {
-        Self::Code(v)
+        Self::Code(Box::new(v))
+    }
+}
+
+/// Jump labels of a loop that is being compiled.
+#[derive(Debug)]
+struct Loop {
+    start: usize,
+    breaks: Vec<usize>,
+    /// Number of enclosing try blocks when the loop started.
+    tries: usize,
+}
+
+/// A try block or catch body that is being compiled, or a finally body
+/// that runs while values are kept on the stack.
+#[derive(Debug)]
+struct TryBlock {
+    /// Start of the currently protected instruction range.
+    start: usize,
+    /// Protected ranges that have been closed, jumping out of the block
+    /// through a `finally` body splits up the protected range.
+    ranges: Vec<(usize, usize)>,
+    finally: Option<Rc<Vec<Statement>>>,
+    /// Depth of the stack when the block was entered, the values above
+    /// it are popped when jumping out of the block.
+    stack: usize,
+}
+
+impl TryBlock {
+    fn close(&mut self, end: usize) {
+        if self.start < end {
+            self.ranges.push((self.start, end));
+        }
     }
 }
 
@@ -57,6 +122,32 @@ pub struct CodeBuilder {
     constants: Vec<Constant>,
     names: Vec<String>,
     parameters: usize,
+    handlers: Vec<Handler>,
+    /// Line table of `(first instruction, line)` pairs, a new pair is
+    /// only added when the line changes.
+    lines: Vec<(usize, usize)>,
+    loops: Vec<Loop>,
+    tries: Vec<TryBlock>,
+    /// Number of values on the stack while a statement starts, the
+    /// iterators of enclosing for loops and the values kept while a
+    /// finally body runs. Handlers truncate the stack to it.
+    stack: usize,
+    line_index: Option<Rc<LineIndex>>,
+    /// Name of the compiled function and of the module it belongs to.
+    name: String,
+    file: String,
+    /// Span of the node that is being compiled.
+    span: Span,
+    /// Whether expression statements echo their value.
+    interactive: bool,
+    /// Variables of the compiled function, `None` for module code where
+    /// every variable is global.
+    scope: Option<FunctionScope>,
+    optimization: OptimizationLevel,
+    folder: Folder,
+    /// The first error found, reported by `compile_*` once the whole
+    /// module is compiled.
+    error: Option<(Span, String)>,
 }
 
 impl CodeBuilder {
@@ -66,9 +157,29 @@ impl CodeBuilder {
             constants: Vec::new(),
             names: Vec::new(),
             parameters,
+            handlers: Vec::new(),
+            lines: Vec::new(),
+            loops: Vec::new(),
+            tries: Vec::new(),
+            stack: 0,
+            line_index: None,
+            name: "<module>".to_string(),
+            file: String::new(),
+            span: Span::default(),
+            interactive: false,
+            scope: None,
+            optimization: OptimizationLevel::default(),
+            folder: Folder::default(),
+            error: None,
         }
     }
 
+    /// Set how much the code is optimized, functions compiled by this
+    /// builder inherit the level.
+    pub fn set_optimization(&mut self, optimization: OptimizationLevel) {
+        self.optimization = optimization;
+    }
+
     fn use_name(&mut self, name: &str) -> NameIndex {
         if let Some(namei) = self.names.iter().position(|n| n == name) {
             namei
@@ -81,7 +192,7 @@ impl CodeBuilder {
     fn use_constant(&mut self, cons: impl Into<Constant>) -> ConstantIndex {
         let cons = cons.into();
 
-        if let Some(consi) = self.constants.iter().position(|c| c == &cons) {
+        if let Some(consi) = self.constants.iter().position(|c| c.is_same(&cons)) {
             consi
         } else {
             self.constants.push(cons);
@@ -89,49 +200,189 @@ impl CodeBuilder {
         }
     }
 
-    pub fn compile_module(&mut self, module: &Module) {
-        for stmt in module.body.iter() {
+    /// Compile `module`, `source` is the text it was parsed from and is
+    /// used to build the line table.
+    pub fn compile_module(&mut self, module: &Module, source: &str) -> Result<(), Diagnostic> {
+        self.compile_body(&module.name, &module.body, source);
+        self.inst(Instruction::Exit(0));
+        self.result(source)
+    }
+
+    /// Compile `module` to return the value of its last statement if that
+    /// is an expression, `null` otherwise. Like interactive chunks, the
+    /// code returns to the caller instead of exiting.
+    pub fn compile_eval(&mut self, module: &Module, source: &str) -> Result<(), Diagnostic> {
+        match module.body.split_last() {
+            Some((
+                last @ Statement {
+                    kind: StatementKind::Expression(expr),
+                    ..
+                },
+                body,
+            )) => {
+                self.compile_body(&module.name, body, source);
+                self.span = last.span;
+                self.compile_expression(expr);
+            }
+            _ => {
+                self.compile_body(&module.name, &module.body, source);
+                self.compile_constant(Constant::Null);
+            }
+        }
+
+        self.inst(Instruction::Return);
+        self.result(source)
+    }
+
+    /// Compile `module` as a chunk of interactive input. Chunks run one
+    /// after another in the same root frame, so a chunk returns instead
+    /// of exiting, and the values of expression statements are echoed.
+    pub fn compile_interactive(&mut self, module: &Module, source: &str) -> Result<(), Diagnostic> {
+        self.interactive = true;
+        self.compile_body(&module.name, &module.body, source);
+        self.compile_constant(Constant::Null);
+        self.inst(Instruction::Return);
+        self.result(source)
+    }
+
+    /// The first error found while compiling `source`.
+    fn result(&mut self, source: &str) -> Result<(), Diagnostic> {
+        match self.error.take() {
+            Some((span, message)) => Err(Diagnostic::new(
+                &self.file, source, span.start, span.end, message,
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Record an error at the node that is being compiled, only the first
+    /// one is reported.
+    fn error(&mut self, message: &str) {
+        if self.error.is_none() {
+            self.error = Some((self.span, message.to_string()));
+        }
+    }
+
+    fn compile_body(&mut self, name: &str, body: &[Statement], source: &str) {
+        self.line_index = Some(Rc::new(LineIndex::new(source)));
+        self.file = name.to_string();
+        self.folder.clear();
+
+        for stmt in body {
             self.compile_statement(stmt);
         }
 
-        self.inst(Instruction::Exit(0));
+        // Code after the last statement belongs to its end.
+        if let Some(last) = body.last() {
+            self.span = Span::new(last.span.end, last.span.end);
+        }
     }
 
     fn inst(&mut self, inst: Instruction) -> usize {
+        if let Some(line_index) = &self.line_index {
+            let line = line_index.line(self.span.start);
+
+            if self.lines.last().map(|(_, l)| *l) != Some(line) {
+                self.lines.push((self.instructions.len(), line));
+            }
+        }
+
         self.instructions.push(inst);
         self.instructions.len() - 1
     }
 
     fn compile_statement(&mut self, stmt: &Statement) {
-        match stmt {
-            Statement::Return(expr) => {
+        let outer_span = std::mem::replace(&mut self.span, stmt.span);
+
+        match &stmt.kind {
+            StatementKind::Return(expr) => {
                 self.compile_expression(expr);
+
+                // The return value stays on the stack while the finally
+                // bodies of the exited try blocks run
+                self.stack += 1;
+                let exited = self.exit_try_blocks(0, true);
                 self.inst(Instruction::Return);
+                self.reenter_try_blocks(exited);
+                self.stack -= 1;
             }
-            Statement::Expression(expr) => {
+            StatementKind::Expression(expr) => {
                 self.compile_expression(expr);
-                self.inst(Instruction::Pop);
+
+                if self.interactive {
+                    self.inst(Instruction::Echo);
+                } else {
+                    self.inst(Instruction::Pop);
+                }
             }
-            Statement::Declaration { ident, assign } => {
+            StatementKind::Declaration { ident, assign } => {
                 if let Some(assign) = assign {
-                    self.compile_expression(assign);
+                    self.compile_named_expression(assign, ident);
                 } else {
                     self.compile_constant(Constant::Null);
                 }
 
-                let namei = self.use_name(ident);
-                self.inst(Instruction::Declare(namei));
+                self.compile_declare(ident);
             }
-            Statement::Assignment { target, source } => self.compile_assignment(target, source),
-            Statement::If {
+            StatementKind::Assignment { target, source } => self.compile_assignment(target, source),
+            StatementKind::If {
                 cond,
                 body,
                 else_body,
             } => self.compile_if_statement(cond, body, else_body),
+            StatementKind::While { cond, body } => self.compile_while_statement(cond, body),
+            StatementKind::For {
+                ident,
+                iterable,
+                body,
+            } => self.compile_for_statement(ident, iterable, body),
+            StatementKind::Break => self.compile_break(),
+            StatementKind::Continue => self.compile_continue(),
+            StatementKind::Throw(expr) => {
+                self.compile_expression(expr);
+                self.inst(Instruction::Throw);
+            }
+            StatementKind::Try {
+                body,
+                catch,
+                finally,
+            } => self.compile_try_statement(body, catch.as_ref(), finally.clone()),
         }
+
+        self.span = outer_span;
+    }
+
+    fn compile_break(&mut self) {
+        let Some(lp) = self.loops.last() else {
+            return self.error("`break` outside of loop");
+        };
+
+        let stack = self.stack;
+        let exited = self.exit_try_blocks(lp.tries, false);
+        let label = self.inst(Instruction::Jump(0));
+        self.loops.last_mut().unwrap().breaks.push(label);
+        self.reenter_try_blocks(exited);
+        self.stack = stack;
+    }
+
+    fn compile_continue(&mut self) {
+        let Some(lp) = self.loops.last() else {
+            return self.error("`continue` outside of loop");
+        };
+        let (start, tries) = (lp.start, lp.tries);
+
+        let stack = self.stack;
+        let exited = self.exit_try_blocks(tries, false);
+        self.inst(Instruction::Jump(start));
+        self.reenter_try_blocks(exited);
+        self.stack = stack;
     }
 
     fn compile_operation(&mut self, lhs: &Expression, op: &Operator, rhs: &Expression) {
+        if let Operator::And | Operator::Or = op {
+            return self.compile_logical_operation(lhs, op, rhs);
+        }
+
         self.compile_expression(rhs);
         self.compile_expression(lhs);
 
@@ -146,46 +397,144 @@ impl CodeBuilder {
             Operator::GreaterThanOrEqual => Instruction::GreaterThanOrEqual,
             Operator::Equals => Instruction::Equals,
             Operator::NotEquals => Instruction::NotEquals,
+            Operator::And | Operator::Or => unreachable!(),
+        };
+
+        self.inst(inst);
+    }
+
+    fn compile_logical_operation(&mut self, lhs: &Expression, op: &Operator, rhs: &Expression) {
+        self.compile_expression(lhs);
+
+        // Keep lhs as result and skip rhs if it decides the outcome
+        let label_short = match op {
+            Operator::And => self.inst(Instruction::JumpFalseOrPop(0)),
+            _ => self.inst(Instruction::JumpTrueOrPop(0)),
+        };
+
+        self.compile_expression(rhs);
+
+        let label_end = self.instructions.len();
+        self.patch_jump(label_short, label_end);
+    }
+
+    fn compile_unary_operation(&mut self, op: &UnaryOperator, expr: &Expression) {
+        self.compile_expression(expr);
+
+        let inst = match op {
+            UnaryOperator::Negate => Instruction::Negate,
+            UnaryOperator::Not => Instruction::Not,
         };
 
         self.inst(inst);
     }
 
     fn compile_assignment(&mut self, target: &AssignmentTarget, source: &Expression) {
-        self.compile_expression(source);
         match target {
-            AssignmentTarget::Identifier(ident) => {
-                let namei = self.use_name(ident);
-                self.inst(Instruction::StoreVariable(namei));
-            }
+            AssignmentTarget::Identifier(ident) => self.compile_named_expression(source, ident),
+            _ => self.compile_expression(source),
+        }
+
+        match target {
+            AssignmentTarget::Identifier(ident) => self.compile_store(ident),
             AssignmentTarget::Property(expr, property) => {
                 self.compile_expression(expr);
 
                 let namei = self.use_name(property);
                 self.inst(Instruction::StoreProperty(namei));
+                self.inst(Instruction::Pop);
             }
             AssignmentTarget::Subscript(expr, subscript) => {
                 self.compile_expression(subscript);
                 self.compile_expression(expr);
                 self.inst(Instruction::StoreSubscript);
+                self.inst(Instruction::Pop);
             }
         }
     }
 
-    fn compile_function(&mut self, params: &[String], body: &[Statement]) {
+    /// Compile an expression assigned to `name`, functions take the name
+    /// for use in stack traces.
+    fn compile_named_expression(&mut self, expr: &Expression, name: &str) {
+        match &expr.kind {
+            ExpressionKind::Function { params, body } => {
+                let outer_span = std::mem::replace(&mut self.span, expr.span);
+                self.compile_function(name, params, body);
+                self.span = outer_span;
+            }
+            _ => self.compile_expression(expr),
+        }
+    }
+
+    fn resolve(&self, name: &str) -> Resolution {
+        match &self.scope {
+            Some(scope) => scope.resolve(name),
+            None => Resolution::Global,
+        }
+    }
+
+    fn compile_load(&mut self, name: &str) {
+        let inst = match self.resolve(name) {
+            Resolution::Local(slot) => Instruction::LoadLocal(slot),
+            Resolution::Cell(index) => Instruction::LoadCell(index),
+            Resolution::Global => Instruction::LoadGlobal(self.use_name(name)),
+        };
+
+        self.inst(inst);
+    }
+
+    fn compile_store(&mut self, name: &str) {
+        let inst = match self.resolve(name) {
+            Resolution::Local(slot) => Instruction::StoreLocal(slot),
+            Resolution::Cell(index) => Instruction::StoreCell(index),
+            Resolution::Global => Instruction::StoreGlobal(self.use_name(name)),
+        };
+
+        self.inst(inst);
+    }
+
+    /// Declare `name` with the value on top of the stack, module variables
+    /// are declared in the scope of the running frame.
+    fn compile_declare(&mut self, name: &str) {
+        let inst = match self.resolve(name) {
+            Resolution::Local(slot) => Instruction::StoreLocal(slot),
+            Resolution::Cell(index) => Instruction::StoreCell(index),
+            Resolution::Global => Instruction::Declare(self.use_name(name)),
+        };
+
+        self.inst(inst);
+    }
+
+    fn compile_function(&mut self, name: &str, params: &[String], body: &[Statement]) {
         let mut code = CodeBuilder::new(params.len());
+        code.name = name.to_string();
+        code.file = self.file.clone();
+        code.line_index = self.line_index.clone();
+        code.span = self.span;
+        code.optimization = self.optimization;
+        code.scope = Some(FunctionScope::new(params, body, self.scope.as_ref()));
 
-        for param in params {
-            code.use_name(param);
+        // Parameters used by nested functions move from their slot to a cell.
+        for (slot, param) in params.iter().enumerate() {
+            if let Resolution::Cell(index) = code.resolve(param) {
+                code.inst(Instruction::LoadLocal(slot));
+                code.inst(Instruction::StoreCell(index));
+            }
         }
 
         for stmt in body {
             code.compile_statement(stmt);
         }
 
+        // The implicit return belongs to the closing brace of the function.
+        code.span = Span::new(self.span.end, self.span.end);
         code.compile_constant(Constant::Null);
         code.inst(Instruction::Return);
 
+        if let Some(error) = code.error.take() {
+            self.error.get_or_insert(error);
+        }
+
         self.compile_constant(code);
         self.inst(Instruction::CreateFunction);
     }
@@ -200,31 +549,43 @@ impl CodeBuilder {
     }
 
     fn compile_expression(&mut self, expr: &Expression) {
-        match expr {
-            Expression::Operation { lhs, op, rhs } => self.compile_operation(lhs, op, rhs),
-            Expression::Integer(int) => self.compile_constant(*int),
-            Expression::Float(flt) => self.compile_constant(*flt),
-            Expression::String(str) => self.compile_constant(str.clone()),
-            Expression::Function { params, body } => self.compile_function(params, body),
-            Expression::FunctionCall { target, args } => self.compile_call(target, args),
-            Expression::Subscript(expr, subscript) => {
+        let outer_span = std::mem::replace(&mut self.span, expr.span);
+
+        match &expr.kind {
+            ExpressionKind::Operation { lhs, op, rhs } => match self.fold(expr) {
+                Some(constant) => self.compile_constant(constant),
+                None => self.compile_operation(lhs, op, rhs),
+            },
+            ExpressionKind::Unary { op, expr: operand } => match self.fold(expr) {
+                Some(constant) => self.compile_constant(constant),
+                None => self.compile_unary_operation(op, operand),
+            },
+            ExpressionKind::Integer(int) => self.compile_constant(*int),
+            ExpressionKind::Float(flt) => self.compile_constant(*flt),
+            ExpressionKind::String(str) => self.compile_constant(str.clone()),
+            ExpressionKind::Bool(b) => self.compile_constant(*b),
+            ExpressionKind::Null => self.compile_constant(Constant::Null),
+            ExpressionKind::Function { params, body } => {
+                self.compile_function("<anonymous>", params, body)
+            }
+            ExpressionKind::FunctionCall { target, args } => self.compile_call(target, args),
+            ExpressionKind::Subscript(expr, subscript) => {
                 self.compile_expression(subscript);
                 self.compile_expression(expr);
                 self.inst(Instruction::LoadSubscript);
             }
-            Expression::Property(expr, property) => {
+            ExpressionKind::Property(expr, property) => {
                 self.compile_expression(expr);
 
                 let namei = self.use_name(property);
                 self.inst(Instruction::LoadProperty(namei));
             }
-            Expression::Object(_items) => todo!(),
-            Expression::List(items) => self.compile_list(items),
-            Expression::Identifier(ident) => {
-                let namei = self.use_name(ident);
-                self.inst(Instruction::LoadVariable(namei));
-            }
+            ExpressionKind::Object(items) => self.compile_object(items),
+            ExpressionKind::List(items) => self.compile_list(items),
+            ExpressionKind::Identifier(ident) => self.compile_load(ident),
         }
+
+        self.span = outer_span;
     }
 
     fn compile_list(&mut self, items: &[Expression]) {
@@ -235,12 +596,36 @@ impl CodeBuilder {
         self.inst(Instruction::CreateList(items.len()));
     }
 
+    fn compile_object(&mut self, items: &[(String, Expression)]) {
+        for (key, value) in items.iter().rev() {
+            self.compile_named_expression(value, key);
+            self.compile_constant(key.clone());
+        }
+
+        self.inst(Instruction::CreateObject(items.len()));
+    }
+
     fn compile_if_statement(
         &mut self,
         cond: &Expression,
         body: &[Statement],
         else_body: &[Statement],
     ) {
+        // Only the code of the branch that runs is kept.
+        if let Some(cond) = self.fold(cond).as_ref().map(optimizer::truthiness) {
+            let (taken, dead) = if cond {
+                (body, else_body)
+            } else {
+                (else_body, body)
+            };
+
+            self.check_statements(dead);
+            for stmt in taken {
+                self.compile_statement(stmt);
+            }
+            return;
+        }
+
         self.compile_expression(cond);
 
         // If false skip over block
@@ -261,10 +646,297 @@ impl CodeBuilder {
             }
 
             let label_else_end = self.instructions.len();
-            *self.instructions.get_mut(label_else).unwrap() = Instruction::Jump(label_else_end - 1);
+            *self.instructions.get_mut(label_else).unwrap() = Instruction::Jump(label_else_end);
         }
 
-        *self.instructions.get_mut(label_start).unwrap() = Instruction::JumpFalse(label_end - 1);
+        *self.instructions.get_mut(label_start).unwrap() = Instruction::JumpFalse(label_end);
+    }
+
+    /// Compile `body` only for its errors and drop the code, so that a
+    /// branch removed by the optimizer is checked like any other.
+    fn check_statements(&mut self, body: &[Statement]) {
+        let instructions = self.instructions.len();
+        let constants = self.constants.len();
+        let names = self.names.len();
+        let handlers = self.handlers.len();
+        let breaks: Vec<usize> = self.loops.iter().map(|lp| lp.breaks.len()).collect();
+        let tries: Vec<(usize, usize)> = self
+            .tries
+            .iter()
+            .map(|block| (block.start, block.ranges.len()))
+            .collect();
+
+        for stmt in body {
+            self.compile_statement(stmt);
+        }
+
+        self.instructions.truncate(instructions);
+        self.constants.truncate(constants);
+        self.names.truncate(names);
+        self.handlers.truncate(handlers);
+        self.lines.retain(|(start, _)| *start < instructions);
+        for (lp, breaks) in self.loops.iter_mut().zip(breaks) {
+            lp.breaks.truncate(breaks);
+        }
+        for (block, (start, ranges)) in self.tries.iter_mut().zip(tries) {
+            block.start = start;
+            block.ranges.truncate(ranges);
+        }
+    }
+
+    fn compile_while_statement(&mut self, cond: &Expression, body: &[Statement]) {
+        let label_start = self.instructions.len();
+        let cond_value = self.fold(cond).as_ref().map(optimizer::truthiness);
+
+        self.loops.push(Loop {
+            start: label_start,
+            breaks: Vec::new(),
+            tries: self.tries.len(),
+        });
+
+        // A loop whose condition is always false is left out, one whose
+        // condition is always true doesn't test it.
+        if cond_value == Some(false) {
+            self.check_statements(body);
+            self.loops.pop();
+            return;
+        }
+
+        // If false skip over loop
+        let label_cond = cond_value.is_none().then(|| {
+            self.compile_expression(cond);
+            self.inst(Instruction::JumpFalse(0))
+        });
+
+        for stmt in body {
+            self.compile_statement(stmt);
+        }
+
+        self.inst(Instruction::Jump(label_start));
+
+        let label_end = self.instructions.len();
+        let lp = self.loops.pop().unwrap();
+
+        for label in lp.breaks.into_iter().chain(label_cond) {
+            self.patch_jump(label, label_end);
+        }
+    }
+
+    fn compile_for_statement(&mut self, ident: &str, iterable: &Expression, body: &[Statement]) {
+        self.compile_expression(iterable);
+        self.inst(Instruction::GetIter);
+
+        // The iterator stays on the stack for the duration of the loop,
+        // when exhausted skip over loop
+        let label_start = self.inst(Instruction::ForIter(0));
+        self.compile_declare(ident);
+        self.stack += 1;
+
+        self.loops.push(Loop {
+            start: label_start,
+            breaks: Vec::new(),
+            tries: self.tries.len(),
+        });
+
+        for stmt in body {
+            self.compile_statement(stmt);
+        }
+
+        self.inst(Instruction::Jump(label_start));
+
+        // Both exhausting the iterator and breaking out of the loop
+        // land here to discard the iterator
+        let label_end = self.inst(Instruction::Pop);
+        let lp = self.loops.pop().unwrap();
+        self.stack -= 1;
+
+        for label in lp.breaks.into_iter().chain([label_start]) {
+            self.patch_jump(label, label_end);
+        }
+    }
+
+    fn compile_try_statement(
+        &mut self,
+        body: &[Statement],
+        catch: Option<&(String, Vec<Statement>)>,
+        finally: Option<Vec<Statement>>,
+    ) {
+        let depth = self.stack;
+        let finally = finally.map(Rc::new);
+
+        let body_ranges = self.compile_try_block(body, finally.clone());
+        self.compile_finally(finally.as_deref());
+        let mut labels_end = vec![self.inst(Instruction::Jump(0))];
+
+        let finally_ranges = if let Some((ident, catch_body)) = catch {
+            self.add_handlers(body_ranges, depth);
+
+            // The error value is pushed by the interpreter
+            self.compile_declare(ident);
+
+            let catch_ranges = if finally.is_some() {
+                self.compile_try_block(catch_body, finally.clone())
+            } else {
+                for stmt in catch_body {
+                    self.compile_statement(stmt);
+                }
+
+                Vec::new()
+            };
+
+            self.compile_finally(finally.as_deref());
+            labels_end.push(self.inst(Instruction::Jump(0)));
+
+            catch_ranges
+        } else {
+            body_ranges
+        };
+
+        if finally.is_some() {
+            // Run finally body on errors and rethrow the error after
+            self.add_handlers(finally_ranges, depth);
+
+            // The error value stays on the stack while the finally body runs
+            self.stack += 1;
+            if let Some(finally) = &finally {
+                self.compile_finally_holding(finally, depth);
+            }
+            self.stack -= 1;
+            self.inst(Instruction::Throw);
+        }
+
+        let label_end = self.instructions.len();
+
+        for label in labels_end {
+            self.patch_jump(label, label_end);
+        }
+    }
+
+    /// Compile `body` as a protected block and return the protected ranges.
+    fn compile_try_block(
+        &mut self,
+        body: &[Statement],
+        finally: Option<Rc<Vec<Statement>>>,
+    ) -> Vec<(usize, usize)> {
+        self.tries.push(TryBlock {
+            start: self.instructions.len(),
+            ranges: Vec::new(),
+            finally,
+            stack: self.stack,
+        });
+
+        for stmt in body {
+            self.compile_statement(stmt);
+        }
+
+        let mut block = self.tries.pop().unwrap();
+        block.close(self.instructions.len());
+        block.ranges
+    }
+
+    fn compile_finally(&mut self, finally: Option<&Vec<Statement>>) {
+        for stmt in finally.into_iter().flatten() {
+            self.compile_statement(stmt);
+        }
+    }
+
+    /// Compile a finally body that runs while the values above `stack` are
+    /// kept on the stack, `break` and `continue` pop them before jumping.
+    fn compile_finally_holding(&mut self, finally: &[Statement], stack: usize) {
+        self.tries.push(TryBlock {
+            start: self.instructions.len(),
+            ranges: Vec::new(),
+            finally: None,
+            stack,
+        });
+
+        for stmt in finally {
+            self.compile_statement(stmt);
+        }
+
+        self.tries.pop();
+    }
+
+    /// Add handlers jumping to the next instruction for `ranges`.
+    fn add_handlers(&mut self, ranges: Vec<(usize, usize)>, depth: usize) {
+        let target = self.instructions.len();
+
+        for (start, end) in ranges {
+            self.handlers.push(Handler {
+                start,
+                end,
+                target,
+                depth,
+            });
+        }
+    }
+
+    /// Leave all try blocks above `depth` before jumping out of them,
+    /// running their finally bodies outside of their protected ranges.
+    /// The values kept on the stack by the blocks are popped, unless
+    /// `returning`, where they stay below the return value.
+    fn exit_try_blocks(&mut self, depth: usize, returning: bool) -> Vec<TryBlock> {
+        let mut exited = Vec::new();
+
+        while self.tries.len() > depth {
+            let mut block = self.tries.pop().unwrap();
+            block.close(self.instructions.len());
+
+            if let Some(finally) = block.finally.clone() {
+                // `break` and `continue` in the finally body refer to the
+                // loops around the try statement.
+                let index = self
+                    .loops
+                    .partition_point(|lp| lp.tries <= self.tries.len());
+                let inner = self.loops.split_off(index);
+
+                if returning {
+                    self.compile_finally_holding(&finally, block.stack);
+                } else {
+                    self.compile_finally(Some(&finally));
+                }
+
+                self.loops.extend(inner);
+            }
+
+            if !returning {
+                for _ in block.stack..self.stack {
+                    self.inst(Instruction::Pop);
+                }
+                self.stack = self.stack.min(block.stack);
+            }
+
+            exited.push(block);
+        }
+
+        exited
+    }
+
+    /// Continue the try blocks left by `exit_try_blocks`.
+    fn reenter_try_blocks(&mut self, exited: Vec<TryBlock>) {
+        for mut block in exited.into_iter().rev() {
+            block.start = self.instructions.len();
+            self.tries.push(block);
+        }
+    }
+
+    fn patch_jump(&mut self, label: usize, target: usize) {
+        match self.instructions.get_mut(label) {
+            Some(Instruction::Jump(jmp))
+            | Some(Instruction::JumpFalse(jmp))
+            | Some(Instruction::JumpFalseOrPop(jmp))
+            | Some(Instruction::JumpTrueOrPop(jmp))
+            | Some(Instruction::ForIter(jmp)) => *jmp = target,
+            _ => unreachable!("expected jump instruction at {}", label),
+        }
+    }
+
+    /// The value of `expr` if it is constant and may be folded.
+    fn fold(&mut self, expr: &Expression) -> Option<Constant> {
+        match self.optimization {
+            OptimizationLevel::None => None,
+            OptimizationLevel::Basic => self.folder.fold(expr),
+        }
     }
 
     fn compile_constant(&mut self, cons: impl Into<Constant>) {
@@ -272,11 +944,39 @@ impl CodeBuilder {
         self.inst(Instruction::LoadConstant(consi));
     }
 
-    pub fn build(self) -> Code {
+    pub fn build(mut self) -> Code {
+        if self.optimization != OptimizationLevel::None {
+            optimizer::peephole(&mut self.instructions, &mut self.handlers, &mut self.lines);
+        }
+
         let constants = self.constants.into_iter().map(|c| c.into()).collect();
         let names = self.names.into_iter().map(Ptr::new).collect();
+        let variables = match &self.scope {
+            Some(scope) => Variables {
+                locals: scope.locals().iter().cloned().map(Ptr::new).collect(),
+                parameters: self.parameters,
+                cells: scope.cells().iter().cloned().map(Ptr::new).collect(),
+                captures: scope.captures().to_vec(),
+            },
+            None => Variables {
+                parameters: self.parameters,
+                ..Variables::default()
+            },
+        };
 
-        Code::new(self.instructions, constants, names, self.parameters)
+        Code::new(
+            self.instructions,
+            constants,
+            names,
+            variables,
+            self.handlers,
+            DebugInfo {
+                name: self.name,
+                file: self.file,
+                lines: self.lines,
+            },
+        )
+        .expect("compiler generated invalid bytecode")
     }
 }
 
@@ -285,3 +985,200 @@ impl PartialEq for CodeBuilder {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, value::Value, Engine, EngineError};
+
+    fn compile(source: &str) -> Code {
+        let module = Parser::new()
+            .parse("test.san", source)
+            .expect("valid syntax");
+        let mut code_builder = CodeBuilder::new(0);
+        code_builder.set_optimization(OptimizationLevel::None);
+        code_builder
+            .compile_module(&module, source)
+            .expect("valid program");
+        code_builder.build()
+    }
+
+    fn eval_int(source: &str) -> i64 {
+        match Engine::new().eval(source).expect("script runs") {
+            Value::Integer(int) => int,
+            _ => panic!("script didn't return an integer"),
+        }
+    }
+
+    #[test]
+    fn line_table() {
+        let code = compile("let a = 1;\n\nlet b = a +\n  2;\nlet f = fn(x) {\n  ^ x;\n};\n");
+
+        // A pair is only added when the line changes, the operands of
+        // `a + 2` are compiled right to left.
+        assert_eq!(code.lines(), [(0, 1), (2, 4), (3, 3), (6, 5), (9, 7)]);
+        let lines: Vec<_> = (0..code.instructions().len())
+            .map(|index| code.line(index))
+            .collect();
+        assert_eq!(
+            lines,
+            [1, 1, 4, 3, 3, 3, 5, 5, 5, 7].map(Some),
+            "{:?}",
+            code.instructions()
+        );
+
+        // The implicit return of a function belongs to its closing brace.
+        let Some(Value::Code(function)) = code.constants().get(2) else {
+            panic!("expected the code of `f`");
+        };
+        let function = function.value();
+        assert_eq!(function.lines(), [(0, 6), (2, 7)]);
+        assert_eq!(function.line(3), Some(7));
+    }
+
+    #[test]
+    fn literals() {
+        let mut engine = Engine::new();
+        let mut eval = |source| engine.eval(source).expect("script runs");
+
+        assert!(matches!(eval("true"), Value::Bool(true)));
+        assert!(matches!(eval("false"), Value::Bool(false)));
+        assert!(matches!(eval("null"), Value::Null));
+        assert!(matches!(eval("0.25"), Value::Float(float) if float == 0.25));
+        assert!(matches!(eval("10.5"), Value::Float(float) if float == 10.5));
+        assert!(matches!(eval("\"a b\""), Value::String(s) if s.value() == "a b"));
+        assert!(matches!(eval("\"\""), Value::String(s) if s.value().is_empty()));
+
+        // Keywords only match whole words.
+        assert!(matches!(
+            eval("let nullable = 1; let trueish = 2; nullable + trueish"),
+            Value::Integer(3)
+        ));
+    }
+
+    #[test]
+    fn object_literals() {
+        assert_eq!(
+            eval_int(
+                "let o = {a: 1, \"b c\": {d: [2, 3]}, get: fn(self) self.a + 10}; o.a + o.get()"
+            ),
+            12
+        );
+        assert_eq!(eval_int("{a: {d: [2, 3]}}.a.d[1]"), 3);
+    }
+
+    #[test]
+    fn object_literal_and_function_bodies() {
+        // `{` after the parameters always opens a block body, an object
+        // literal is only allowed after the leftmost term of an
+        // expression body or in parentheses.
+        assert_eq!(eval_int("let f = fn() { ^ {a: 1}; }; f().a"), 1);
+        assert_eq!(eval_int("let f = fn() ({a: 2}); f().a"), 2);
+        assert_eq!(eval_int("let f = fn(x) x + {a: 3}.a; f(1)"), 4);
+        assert!(matches!(
+            Engine::new().eval("let f = fn() {}; f()"),
+            Ok(Value::Null)
+        ));
+        assert!(matches!(
+            Engine::new().eval("let f = fn() {a: 1};"),
+            Err(EngineError::Syntax(_))
+        ));
+    }
+
+    #[test]
+    fn while_loops() {
+        let sum = "let i = 0; let s = 0;
+            while i < 10 {
+                i = i + 1;
+                if i == 3 { continue; }
+                if i > 5 { break; }
+                s = s + i;
+            }
+            s";
+        assert_eq!(eval_int(sum), 12);
+
+        let nested = "let n = 0; let i = 0;
+            while i < 3 {
+                i = i + 1;
+                let j = 0;
+                while true { j = j + 1; if j == i { break; } n = n + 1; }
+            }
+            n";
+        assert_eq!(eval_int(nested), 3);
+    }
+
+    #[test]
+    fn continue_in_for() {
+        let source = "let s = 0; for x in [1, 2, 3, 4] { if x == 2 { continue; } s = s + x; } s";
+        assert_eq!(eval_int(source), 8);
+    }
+
+    #[test]
+    fn break_and_continue_outside_of_loop() {
+        for source in [
+            "break;",
+            "if true { continue; }",
+            "while true { let f = fn() { break; }; }",
+        ] {
+            assert!(
+                matches!(Engine::new().eval(source), Err(EngineError::Syntax(_))),
+                "`{}` compiled",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn break_in_finally() {
+        let normal = "let n = 0; while true { try { n = 1; } finally { break; } } n";
+        assert_eq!(eval_int(normal), 1);
+
+        let error = "let n = 0; while true { try { throw \"x\"; } finally { n = 2; break; } } n";
+        assert_eq!(eval_int(error), 2);
+
+        let nested = "let n = 0;
+            for i in [1] {
+                try { throw \"x\"; } finally { try { throw \"y\"; } finally { n = i; break; } }
+            }
+            n";
+        assert_eq!(eval_int(nested), 1);
+    }
+
+    #[test]
+    fn continue_in_finally() {
+        let normal = "let n = 0; for i in [1, 2, 3] { try { n = n + i; } finally { continue; } } n";
+        assert_eq!(eval_int(normal), 6);
+
+        let error =
+            "let n = 0; for i in [1, 2, 3] { try { throw \"x\"; } finally { n = n + i; continue; } } n";
+        assert_eq!(eval_int(error), 6);
+    }
+
+    #[test]
+    fn return_in_finally() {
+        let normal = "let f = fn() { try { } finally { ^ 1; } }; f()";
+        assert_eq!(eval_int(normal), 1);
+
+        let error = "let f = fn() { for i in [2] { try { throw \"x\"; } finally { ^ i; } } }; f()";
+        assert_eq!(eval_int(error), 2);
+    }
+
+    #[test]
+    fn jump_out_of_finally_run_by_return() {
+        // The finally body is run by `^`, `break` leaves the loop around
+        // the try statement and discards the return value.
+        let f = "let f = fn() {
+                while true { try { for i in [1] { ^ 1; } } finally { break; } }
+                ^ 2;
+            };
+            f()";
+        assert_eq!(eval_int(f), 2);
+
+        let f = "let f = fn() {
+                for i in [1] { try { ^ 1; } finally { try { throw \"x\"; } finally { continue; } } }
+                ^ 3;
+            };
+            f()";
+        assert_eq!(eval_int(f), 3);
+    }
+}
@@ -0,0 +1,502 @@
+use std::collections::HashSet;
+
+use super::Constant;
+use crate::{
+    ast::{Expression, ExpressionKind, Operator, UnaryOperator},
+    frame::Handler,
+    instruction::Instruction,
+};
+
+/// How much `CodeBuilder` optimizes the code it builds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptimizationLevel {
+    /// Compile every expression and statement as written.
+    None,
+    /// Fold constant expressions, drop the branches of `if` statements
+    /// and the `while` loops that a constant condition rules out, and run
+    /// the peephole pass.
+    #[default]
+    Basic,
+}
+
+/// Folds constant expressions. The compiler asks for every node of an
+/// expression tree from the top down, so the nodes that can't be folded
+/// are remembered, and asking for their children doesn't fold the whole
+/// subtree again. Folded values are moved to the caller instead.
+#[derive(Debug, Default)]
+pub struct Folder {
+    failed: HashSet<*const Expression>,
+}
+
+impl Folder {
+    /// The value of `expr` if it can be computed at compile time. Operations
+    /// that would raise an error, like a division by zero, are left to the
+    /// interpreter so the error is raised at the right time.
+    pub fn fold(&mut self, expr: &Expression) -> Option<Constant> {
+        if self.failed.contains(&(expr as *const Expression)) {
+            return None;
+        }
+
+        let folded = self.fold_node(expr);
+        if folded.is_none() {
+            self.failed.insert(expr);
+        }
+
+        folded
+    }
+
+    /// Forget the nodes of the expressions folded so far.
+    pub fn clear(&mut self) {
+        self.failed.clear();
+    }
+
+    fn fold_node(&mut self, expr: &Expression) -> Option<Constant> {
+        match &expr.kind {
+            ExpressionKind::Integer(int) => Some(Constant::Integer(*int)),
+            ExpressionKind::Float(float) => Some(Constant::Float(*float)),
+            ExpressionKind::String(str) => Some(Constant::String(str.clone())),
+            ExpressionKind::Bool(b) => Some(Constant::Bool(*b)),
+            ExpressionKind::Null => Some(Constant::Null),
+            ExpressionKind::Unary { op, expr } => fold_unary(op, self.fold(expr)?),
+            ExpressionKind::Operation { lhs, op, rhs } => match op {
+                Operator::And | Operator::Or => {
+                    let lhs = self.fold(lhs)?;
+                    let rhs = self.fold(rhs)?;
+
+                    // The deciding operand is the result.
+                    if truthiness(&lhs) == matches!(op, Operator::And) {
+                        Some(rhs)
+                    } else {
+                        Some(lhs)
+                    }
+                }
+                _ => fold_operation(self.fold(lhs)?, op, self.fold(rhs)?),
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Whether `constant` counts as true in a condition, like `Value::as_bool`.
+pub fn truthiness(constant: &Constant) -> bool {
+    match constant {
+        Constant::Null => false,
+        Constant::Bool(b) => *b,
+        Constant::Integer(int) => *int != 0,
+        Constant::Float(float) => *float != 0.0,
+        Constant::String(str) => !str.is_empty(),
+        Constant::Code(_) => true,
+    }
+}
+
+fn fold_unary(op: &UnaryOperator, constant: Constant) -> Option<Constant> {
+    match (op, constant) {
+        (UnaryOperator::Negate, Constant::Integer(int)) => int.checked_neg().map(Constant::Integer),
+        (UnaryOperator::Negate, Constant::Float(float)) => Some(Constant::Float(-float)),
+        (UnaryOperator::Not, constant) => Some(Constant::Bool(!truthiness(&constant))),
+        _ => None,
+    }
+}
+
+/// Apply `op` like the builtin types do: the type of `lhs` decides the
+/// operation and `rhs` is converted to it.
+fn fold_operation(lhs: Constant, op: &Operator, rhs: Constant) -> Option<Constant> {
+    if let Operator::Equals | Operator::NotEquals = op {
+        let equals = equals(&lhs, &rhs)?;
+        return Some(Constant::Bool(equals == matches!(op, Operator::Equals)));
+    }
+
+    match (lhs, rhs) {
+        (Constant::Integer(lhs), Constant::Integer(rhs)) => fold_integer(lhs, op, rhs),
+        (Constant::Integer(lhs), Constant::Float(rhs)) => fold_integer(lhs, op, rhs as i64),
+        (Constant::Float(lhs), Constant::Float(rhs)) => fold_float(lhs, op, rhs),
+        (Constant::Float(lhs), Constant::Integer(rhs)) => fold_float(lhs, op, rhs as f64),
+        (Constant::String(lhs), Constant::String(rhs)) if matches!(op, Operator::Add) => {
+            Some(Constant::String(lhs + &rhs))
+        }
+        _ => None,
+    }
+}
+
+fn fold_integer(lhs: i64, op: &Operator, rhs: i64) -> Option<Constant> {
+    let int = match op {
+        Operator::Add => lhs.checked_add(rhs)?,
+        Operator::Subtract => lhs.checked_sub(rhs)?,
+        Operator::Multiply => lhs.checked_mul(rhs)?,
+        Operator::Divide => lhs.checked_div(rhs)?,
+        _ => return compare(lhs.partial_cmp(&rhs), op),
+    };
+
+    Some(Constant::Integer(int))
+}
+
+fn fold_float(lhs: f64, op: &Operator, rhs: f64) -> Option<Constant> {
+    let float = match op {
+        Operator::Add => lhs + rhs,
+        Operator::Subtract => lhs - rhs,
+        Operator::Multiply => lhs * rhs,
+        Operator::Divide => lhs / rhs,
+        _ => return compare(lhs.partial_cmp(&rhs), op),
+    };
+
+    Some(Constant::Float(float))
+}
+
+fn compare(ordering: Option<std::cmp::Ordering>, op: &Operator) -> Option<Constant> {
+    let result = match op {
+        Operator::LessThan => ordering.is_some_and(|ordering| ordering.is_lt()),
+        Operator::GreaterThan => ordering.is_some_and(|ordering| ordering.is_gt()),
+        Operator::LessThanOrEqual => ordering.is_some_and(|ordering| ordering.is_le()),
+        Operator::GreaterThanOrEqual => ordering.is_some_and(|ordering| ordering.is_ge()),
+        _ => return None,
+    };
+
+    Some(Constant::Bool(result))
+}
+
+/// Equality of two constants like the builtin `==`.
+fn equals(lhs: &Constant, rhs: &Constant) -> Option<bool> {
+    let equals = match (lhs, rhs) {
+        (Constant::Bool(l), Constant::Bool(r)) => l == r,
+        (Constant::Bool(l), Constant::Integer(r)) => *l == (*r != 0),
+        (Constant::Bool(l), Constant::Float(r)) => *l == (*r != 0.0),
+        (Constant::Integer(l), Constant::Integer(r)) => l == r,
+        (Constant::Integer(l), Constant::Float(r)) => *l as f64 == *r,
+        (Constant::Integer(l), Constant::Bool(r)) => (*l != 0) == *r,
+        (Constant::Float(l), Constant::Float(r)) => l == r,
+        (Constant::Float(l), Constant::Integer(r)) => *l == *r as f64,
+        (Constant::Float(l), Constant::Bool(r)) => (*l != 0.0) == *r,
+        (Constant::String(l), Constant::String(r)) => l == r,
+        (Constant::Null, Constant::Null) => true,
+        (Constant::Code(_), _) | (_, Constant::Code(_)) => return None,
+        _ => false,
+    };
+
+    Some(equals)
+}
+
+/// Remove unreachable instructions and jumps to the next instruction,
+/// keeping jump targets, handlers and the line table in sync.
+pub fn peephole(
+    instructions: &mut Vec<Instruction>,
+    handlers: &mut Vec<Handler>,
+    lines: &mut Vec<(usize, usize)>,
+) {
+    loop {
+        let mut keep = reachable(instructions, handlers);
+
+        for (index, instruction) in instructions.iter().enumerate() {
+            if let Instruction::Jump(target) = instruction {
+                // Only instructions between the jump and its target can be
+                // dropped, so the target is the next kept instruction.
+                let forward = *target > index;
+                if forward && keep[index] && (index + 1..*target).all(|between| !keep[between]) {
+                    keep[index] = false;
+                }
+            }
+        }
+
+        if keep.iter().all(|keep| *keep) {
+            break;
+        }
+
+        remove(instructions, handlers, lines, &keep);
+    }
+}
+
+/// Instructions that can run, starting from the first one.
+fn reachable(instructions: &[Instruction], handlers: &[Handler]) -> Vec<bool> {
+    let mut reachable = vec![false; instructions.len()];
+    let mut pending = vec![0];
+
+    loop {
+        while let Some(index) = pending.pop() {
+            if index >= instructions.len() || reachable[index] {
+                continue;
+            }
+
+            reachable[index] = true;
+
+            match instructions[index] {
+                Instruction::Return | Instruction::Exit(_) | Instruction::Throw => {}
+                Instruction::Jump(target) => pending.push(target),
+                Instruction::JumpFalse(target)
+                | Instruction::JumpFalseOrPop(target)
+                | Instruction::JumpTrueOrPop(target)
+                | Instruction::ForIter(target) => pending.extend([target, index + 1]),
+                _ => pending.push(index + 1),
+            }
+        }
+
+        // A handler runs if any instruction it protects does.
+        let before = pending.len();
+        for handler in handlers {
+            let protects = (handler.start..handler.end).any(|index| reachable[index]);
+
+            if protects && !reachable[handler.target] {
+                pending.push(handler.target);
+            }
+        }
+
+        if pending.len() == before {
+            return reachable;
+        }
+    }
+}
+
+fn remove(
+    instructions: &mut Vec<Instruction>,
+    handlers: &mut Vec<Handler>,
+    lines: &mut Vec<(usize, usize)>,
+    keep: &[bool],
+) {
+    // `moved[index]` is the new index of the first kept instruction at or
+    // after `index`, removed jumps were to the next kept instruction.
+    let mut moved = vec![0; keep.len() + 1];
+    let mut kept = 0;
+    for (index, keep) in keep.iter().enumerate() {
+        moved[index] = kept;
+        kept += *keep as usize;
+    }
+    moved[keep.len()] = kept;
+
+    let mut index = 0;
+    instructions.retain(|_| {
+        index += 1;
+        keep[index - 1]
+    });
+
+    for instruction in instructions.iter_mut() {
+        match instruction {
+            Instruction::Jump(target)
+            | Instruction::JumpFalse(target)
+            | Instruction::JumpFalseOrPop(target)
+            | Instruction::JumpTrueOrPop(target)
+            | Instruction::ForIter(target) => *target = moved[*target],
+            _ => {}
+        }
+    }
+
+    for handler in handlers.iter_mut() {
+        handler.start = moved[handler.start];
+        handler.end = moved[handler.end];
+        handler.target = moved[handler.target];
+    }
+    handlers.retain(|handler| handler.start < handler.end);
+
+    let mut moved_lines: Vec<(usize, usize)> = Vec::with_capacity(lines.len());
+    for (start, line) in lines.iter() {
+        let start = moved[*start];
+
+        // Entries of removed instructions are replaced by the next entry.
+        if moved_lines.last().is_some_and(|(last, _)| *last == start) {
+            moved_lines.pop();
+        }
+        if moved_lines.last().map(|(_, last)| last) != Some(line) && start < kept {
+            moved_lines.push((start, *line));
+        }
+    }
+    *lines = moved_lines;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ast::StatementKind, parser::Parser, value::Value, CodeBuilder, Engine, OptimizationLevel,
+    };
+
+    /// Fold `expr` with `folder`.
+    fn fold_with(folder: &mut Folder, expr: &str) -> Option<Constant> {
+        let source = format!("let x = {};", expr);
+        let module = Parser::new()
+            .parse("test.san", &source)
+            .expect("valid syntax");
+
+        match &module.body[0].kind {
+            StatementKind::Declaration {
+                assign: Some(expr), ..
+            } => folder.fold(expr),
+            _ => unreachable!("parsed a declaration"),
+        }
+    }
+
+    fn fold_source(expr: &str) -> Option<Constant> {
+        fold_with(&mut Folder::default(), expr)
+    }
+
+    #[test]
+    fn folds_constant_expressions() {
+        assert_eq!(fold_source("1 + 2 * 3"), Some(Constant::Integer(7)));
+        assert_eq!(fold_source("1.5 * 2"), Some(Constant::Float(3.0)));
+        assert_eq!(fold_source("-(2 - 5)"), Some(Constant::Integer(3)));
+        assert_eq!(
+            fold_source("1 < 2 && \"\""),
+            Some(Constant::String(String::new()))
+        );
+        assert_eq!(
+            fold_source("null || 0 || \"a\""),
+            Some(Constant::String("a".to_string()))
+        );
+        assert_eq!(fold_source("!\"\""), Some(Constant::Bool(true)));
+        assert_eq!(fold_source("1 == 1.0"), Some(Constant::Bool(true)));
+    }
+
+    #[test]
+    fn leaves_errors_and_variables_to_the_interpreter() {
+        assert_eq!(fold_source("1 / 0"), None);
+        assert_eq!(fold_source("9223372036854775807 + 1"), None);
+        assert_eq!(fold_source("x + 1"), None);
+        assert_eq!(fold_source("[1] && 2"), None);
+    }
+
+    #[test]
+    fn remembers_nodes_that_cant_be_folded() {
+        // `x`, `x + 1`, `x + 1 + 2` and the whole sum can't be folded, the
+        // compiler asks for each of them without folding the rest again.
+        let mut folder = Folder::default();
+        assert_eq!(fold_with(&mut folder, "x + 1 + 2 + 3"), None);
+        assert_eq!(folder.failed.len(), 4);
+
+        folder.clear();
+        assert!(folder.failed.is_empty());
+    }
+
+    #[test]
+    fn peephole_removes_dead_code() {
+        // Jump over `LoadConstant(0)` to the next kept instruction, the
+        // `Pop` after the `Return` can't run.
+        let mut instructions = vec![
+            Instruction::Jump(2),
+            Instruction::LoadConstant(0),
+            Instruction::LoadConstant(0),
+            Instruction::Return,
+            Instruction::Pop,
+        ];
+        let mut handlers = Vec::new();
+        let mut lines = vec![(0, 1), (2, 2), (4, 3)];
+
+        peephole(&mut instructions, &mut handlers, &mut lines);
+
+        assert!(matches!(
+            instructions[..],
+            [Instruction::LoadConstant(0), Instruction::Return]
+        ));
+        assert_eq!(lines, [(0, 2)]);
+    }
+
+    fn compile(source: &str, optimization: OptimizationLevel) -> usize {
+        let module = Parser::new()
+            .parse("test.san", source)
+            .expect("valid syntax");
+        let mut code_builder = CodeBuilder::new(0);
+        code_builder.set_optimization(optimization);
+        code_builder
+            .compile_module(&module, source)
+            .expect("valid program");
+        code_builder.build().instructions().len()
+    }
+
+    #[test]
+    fn optimized_code_is_shorter_and_behaves_the_same() {
+        let source = "let r = 0;
+            if 1 > 2 { r = 1; } else { r = 2 * 3 + 1; }
+            while false { r = 0; }
+            r = r + (4 - 2);
+            r;";
+
+        assert!(
+            compile(source, OptimizationLevel::Basic) < compile(source, OptimizationLevel::None)
+        );
+
+        for optimization in [OptimizationLevel::None, OptimizationLevel::Basic] {
+            let mut engine = Engine::new();
+            engine.set_optimization(optimization);
+            let value = engine.eval(source).expect("script runs");
+            assert!(matches!(value, Value::Integer(9)));
+        }
+    }
+
+    #[test]
+    fn negative_zero_keeps_its_own_constant() {
+        for optimization in [OptimizationLevel::None, OptimizationLevel::Basic] {
+            let mut engine = Engine::new();
+            engine.set_optimization(optimization);
+            let value = engine
+                .eval("let a = 0.0; let z = -0.0; 1.0 / z")
+                .expect("script runs");
+            assert!(matches!(value, Value::Float(float) if float == f64::NEG_INFINITY));
+        }
+    }
+
+    #[test]
+    fn dead_branches_are_checked() {
+        for optimization in [OptimizationLevel::None, OptimizationLevel::Basic] {
+            for source in [
+                "if false { break; }",
+                "if true { } else { continue; }",
+                "if 1 > 2 { let f = fn() { break; }; }",
+            ] {
+                let module = Parser::new()
+                    .parse("test.san", source)
+                    .expect("valid syntax");
+                let mut code_builder = CodeBuilder::new(0);
+                code_builder.set_optimization(optimization);
+                assert!(
+                    code_builder.compile_module(&module, source).is_err(),
+                    "`{}` compiled at {:?}",
+                    source,
+                    optimization
+                );
+            }
+
+            // A dead `break` in a loop leaves no jump behind.
+            let mut engine = Engine::new();
+            engine.set_optimization(optimization);
+            for source in [
+                "let n = 0; while n < 3 { if false { break; } n = n + 1; } n",
+                "let n = 0; while n < 3 { try { if false { break; } n = n + 1; } finally { } } n",
+            ] {
+                let value = engine.eval(source).expect("script runs");
+                assert!(matches!(value, Value::Integer(3)));
+            }
+        }
+    }
+
+    #[test]
+    fn constant_loop_conditions() {
+        assert_eq!(
+            compile(
+                "let r = 1; while 1 > 2 { r = 2; }",
+                OptimizationLevel::Basic
+            ),
+            compile("let r = 1;", OptimizationLevel::Basic)
+        );
+        let source = "while true { break; }";
+        assert!(
+            compile(source, OptimizationLevel::Basic) < compile(source, OptimizationLevel::None)
+        );
+
+        for optimization in [OptimizationLevel::None, OptimizationLevel::Basic] {
+            let mut engine = Engine::new();
+            engine.set_optimization(optimization);
+            let value = engine
+                .eval("let n = 0; while true { n = n + 1; if n == 3 { break; } } n")
+                .expect("script runs");
+            assert!(matches!(value, Value::Integer(3)));
+            let value = engine
+                .eval("while false { n = 0; continue; } n")
+                .expect("script runs");
+            assert!(matches!(value, Value::Integer(3)));
+
+            // The body of a loop that never runs is still checked.
+            let source = "while false { let f = fn() { break; }; }";
+            let module = Parser::new()
+                .parse("test.san", source)
+                .expect("valid syntax");
+            let mut code_builder = CodeBuilder::new(0);
+            code_builder.set_optimization(optimization);
+            assert!(code_builder.compile_module(&module, source).is_err());
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::HashSet;
+
+use crate::ast::{AssignmentTarget, Expression, ExpressionKind, Statement, StatementKind};
+
+/// Where the value of a variable lives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// A slot in the locals of the running frame.
+    Local(usize),
+    /// A cell shared with nested functions, either created by the running
+    /// frame or captured from an enclosing function.
+    Cell(usize),
+    /// A module variable or a builtin.
+    Global,
+}
+
+/// The variables of a function, resolved before its body is compiled.
+#[derive(Debug, Default)]
+pub struct FunctionScope {
+    /// Names of the local slots, parameters first.
+    locals: Vec<String>,
+    /// Names of the cells, the variables declared by this function that
+    /// nested functions use come first, then the captured variables.
+    cells: Vec<String>,
+    /// For every captured variable, the index of its cell in the
+    /// enclosing function.
+    captures: Vec<usize>,
+}
+
+impl FunctionScope {
+    pub fn new(params: &[String], body: &[Statement], enclosing: Option<&FunctionScope>) -> Self {
+        let declared = declarations(params, body);
+
+        let mut uses = Uses::default();
+        uses.statements(body);
+        let mut cells: Vec<String> = declared
+            .iter()
+            .filter(|name| uses.nested.contains(*name))
+            .cloned()
+            .collect();
+
+        // Arguments are passed in slots, so parameters always get one.
+        let mut locals = params.to_vec();
+        locals.extend(
+            declared
+                .into_iter()
+                .filter(|name| !params.contains(name) && !cells.contains(name)),
+        );
+
+        // Sorted, so the same source always compiles to the same code.
+        let mut free: Vec<(usize, String)> = free_names(params, body)
+            .into_iter()
+            .filter_map(|name| match enclosing?.resolve(&name) {
+                Resolution::Cell(index) => Some((index, name)),
+                _ => None,
+            })
+            .collect();
+        free.sort();
+
+        let captures = free.iter().map(|(index, _)| *index).collect();
+        cells.extend(free.into_iter().map(|(_, name)| name));
+
+        Self {
+            locals,
+            cells,
+            captures,
+        }
+    }
+
+    pub fn resolve(&self, name: &str) -> Resolution {
+        if let Some(index) = self.cells.iter().position(|cell| cell == name) {
+            Resolution::Cell(index)
+        } else if let Some(slot) = self.locals.iter().position(|local| local == name) {
+            Resolution::Local(slot)
+        } else {
+            Resolution::Global
+        }
+    }
+
+    pub fn locals(&self) -> &[String] {
+        &self.locals
+    }
+
+    pub fn cells(&self) -> &[String] {
+        &self.cells
+    }
+
+    pub fn captures(&self) -> &[usize] {
+        &self.captures
+    }
+}
+
+/// The parameters and the names declared in `body` in order, without
+/// descending into nested functions.
+fn declarations(params: &[String], body: &[Statement]) -> Vec<String> {
+    fn declare(declared: &mut Vec<String>, name: &str) {
+        if !declared.iter().any(|declared| declared == name) {
+            declared.push(name.to_string());
+        }
+    }
+
+    fn visit(stmts: &[Statement], declared: &mut Vec<String>) {
+        for stmt in stmts {
+            match &stmt.kind {
+                StatementKind::Declaration { ident, .. } => declare(declared, ident),
+                StatementKind::If {
+                    body, else_body, ..
+                } => {
+                    visit(body, declared);
+                    visit(else_body, declared);
+                }
+                StatementKind::While { body, .. } => visit(body, declared),
+                StatementKind::For { ident, body, .. } => {
+                    declare(declared, ident);
+                    visit(body, declared);
+                }
+                StatementKind::Try {
+                    body,
+                    catch,
+                    finally,
+                } => {
+                    visit(body, declared);
+
+                    if let Some((ident, body)) = catch {
+                        declare(declared, ident);
+                        visit(body, declared);
+                    }
+
+                    visit(finally.as_deref().unwrap_or_default(), declared);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut declared = Vec::new();
+    for param in params {
+        declare(&mut declared, param);
+    }
+    visit(body, &mut declared);
+    declared
+}
+
+/// Names used by a function that it doesn't declare itself.
+fn free_names(params: &[String], body: &[Statement]) -> HashSet<String> {
+    let declared = declarations(params, body);
+
+    let mut uses = Uses::default();
+    uses.statements(body);
+
+    uses.direct
+        .into_iter()
+        .chain(uses.nested)
+        .filter(|name| !declared.contains(name))
+        .collect()
+}
+
+/// Names used by a function body.
+#[derive(Default)]
+struct Uses {
+    /// Names used by the body itself.
+    direct: HashSet<String>,
+    /// Free names of the functions nested in the body.
+    nested: HashSet<String>,
+}
+
+impl Uses {
+    fn statements(&mut self, stmts: &[Statement]) {
+        for stmt in stmts {
+            self.statement(stmt);
+        }
+    }
+
+    fn statement(&mut self, stmt: &Statement) {
+        match &stmt.kind {
+            StatementKind::Expression(expr)
+            | StatementKind::Return(expr)
+            | StatementKind::Throw(expr) => self.expression(expr),
+            StatementKind::Assignment { target, source } => {
+                match target {
+                    AssignmentTarget::Identifier(ident) => {
+                        self.direct.insert(ident.clone());
+                    }
+                    AssignmentTarget::Property(expr, _) => self.expression(expr),
+                    AssignmentTarget::Subscript(expr, subscript) => {
+                        self.expression(expr);
+                        self.expression(subscript);
+                    }
+                }
+                self.expression(source);
+            }
+            StatementKind::Declaration { assign, .. } => {
+                if let Some(assign) = assign {
+                    self.expression(assign);
+                }
+            }
+            StatementKind::If {
+                cond,
+                body,
+                else_body,
+            } => {
+                self.expression(cond);
+                self.statements(body);
+                self.statements(else_body);
+            }
+            StatementKind::While { cond, body } => {
+                self.expression(cond);
+                self.statements(body);
+            }
+            StatementKind::For { iterable, body, .. } => {
+                self.expression(iterable);
+                self.statements(body);
+            }
+            StatementKind::Break | StatementKind::Continue => {}
+            StatementKind::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                self.statements(body);
+
+                if let Some((_, body)) = catch {
+                    self.statements(body);
+                }
+
+                self.statements(finally.as_deref().unwrap_or_default());
+            }
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression) {
+        match &expr.kind {
+            ExpressionKind::Identifier(ident) => {
+                self.direct.insert(ident.clone());
+            }
+            ExpressionKind::Integer(_)
+            | ExpressionKind::Float(_)
+            | ExpressionKind::String(_)
+            | ExpressionKind::Bool(_)
+            | ExpressionKind::Null => {}
+            ExpressionKind::List(items) => {
+                for item in items {
+                    self.expression(item);
+                }
+            }
+            ExpressionKind::Object(items) => {
+                for (_, value) in items {
+                    self.expression(value);
+                }
+            }
+            ExpressionKind::Property(expr, _) => self.expression(expr),
+            ExpressionKind::Subscript(expr, subscript) => {
+                self.expression(expr);
+                self.expression(subscript);
+            }
+            ExpressionKind::Function { params, body } => {
+                self.nested.extend(free_names(params, body));
+            }
+            ExpressionKind::FunctionCall { target, args } => {
+                self.expression(target);
+
+                for arg in args {
+                    self.expression(arg);
+                }
+            }
+            ExpressionKind::Operation { lhs, rhs, .. } => {
+                self.expression(lhs);
+                self.expression(rhs);
+            }
+            ExpressionKind::Unary { expr, .. } => self.expression(expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, value::Value, Engine};
+
+    fn scope(params: &[&str], source: &str, enclosing: Option<&FunctionScope>) -> FunctionScope {
+        let module = Parser::new()
+            .parse("test.san", source)
+            .expect("valid syntax");
+        let params: Vec<String> = params.iter().map(|param| param.to_string()).collect();
+        FunctionScope::new(&params, &module.body, enclosing)
+    }
+
+    #[test]
+    fn resolves_locals_cells_and_globals() {
+        let scope = scope(
+            &["a"],
+            "let b = 1; let c = fn() b; for i in [a] { print(i); }",
+            None,
+        );
+
+        assert_eq!(scope.locals(), ["a", "c", "i"]);
+        assert_eq!(scope.cells(), ["b"]);
+        assert_eq!(scope.resolve("a"), Resolution::Local(0));
+        assert_eq!(scope.resolve("b"), Resolution::Cell(0));
+        assert_eq!(scope.resolve("i"), Resolution::Local(2));
+        assert_eq!(scope.resolve("print"), Resolution::Global);
+    }
+
+    #[test]
+    fn captures_cells_of_the_enclosing_function() {
+        let outer = scope(&[], "let x = 1; let y = 2; let f = fn() x + y;", None);
+        let inner = scope(&[], "^ y + x + z;", Some(&outer));
+
+        // Captured cells are sorted by their index in the outer function.
+        assert_eq!(inner.cells(), ["x", "y"]);
+        assert_eq!(
+            inner.captures(),
+            [outer.cells().len() - 2, outer.cells().len() - 1]
+        );
+        assert_eq!(inner.resolve("y"), Resolution::Cell(1));
+        assert_eq!(inner.resolve("z"), Resolution::Global);
+    }
+
+    #[test]
+    fn parameters_used_by_nested_functions_are_cells() {
+        let scope = scope(&["n"], "^ fn() n;", None);
+
+        // The argument is passed in a slot and copied into the cell.
+        assert_eq!(scope.locals(), ["n"]);
+        assert_eq!(scope.resolve("n"), Resolution::Cell(0));
+    }
+
+    #[test]
+    fn closures_share_cells() {
+        let mut engine = Engine::new();
+        let value = engine
+            .eval(
+                "let counter = fn() {
+                    let n = 0;
+                    ^ { inc: fn(self) { n = n + 1; ^ n; }, get: fn(self) n };
+                };
+                let a = counter();
+                let b = counter();
+                a.inc(); a.inc(); b.inc();
+                a.get() * 10 + b.get()",
+            )
+            .expect("script runs");
+
+        assert!(matches!(value, Value::Integer(21)));
+    }
+
+    #[test]
+    fn nested_closures_capture_through_their_parents() {
+        let mut engine = Engine::new();
+        let value = engine
+            .eval(
+                "let f = fn(x) { let g = fn() { let h = fn() x; ^ h(); }; x = x + 1; ^ g(); }; f(1)",
+            )
+            .expect("script runs");
+
+        assert!(matches!(value, Value::Integer(2)));
+    }
+}
@@ -0,0 +1,338 @@
+//! Conversions between Rust values and `Value`, used to write native
+//! functions with ordinary Rust signatures, see `Value::native_fn`.
+
+use std::collections::HashMap;
+
+use crate::{
+    error::{ErrorKind, RuntimeError},
+    ptr::Ptr,
+    value::{ArgPattern, Object, Value},
+    Interpreter,
+};
+
+/// A Rust value that can be converted from a `Value`.
+pub trait FromValue: Sized {
+    /// Convert `value`, failing with a `TypeError` if it has the wrong type.
+    fn from_value(value: &Value, interp: &Interpreter) -> Result<Self, RuntimeError>;
+}
+
+/// A Rust value that can be converted into a `Value`.
+pub trait IntoValue {
+    fn into_value(self, interp: &Interpreter) -> Value;
+}
+
+/// The return value of a native function, either a value or a `Result`
+/// whose error is raised in the script.
+pub trait IntoNativeResult {
+    fn into_native_result(self, interp: &Interpreter) -> Result<Value, RuntimeError>;
+}
+
+impl<T: IntoValue> IntoNativeResult for T {
+    fn into_native_result(self, interp: &Interpreter) -> Result<Value, RuntimeError> {
+        Ok(self.into_value(interp))
+    }
+}
+
+impl<T: IntoValue> IntoNativeResult for Result<T, RuntimeError> {
+    fn into_native_result(self, interp: &Interpreter) -> Result<Value, RuntimeError> {
+        self.map(|value| value.into_value(interp))
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value, _interp: &Interpreter) -> Result<Self, RuntimeError> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self, _interp: &Interpreter) -> Value {
+        self
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self, _interp: &Interpreter) -> Value {
+        Value::Null
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value, interp: &Interpreter) -> Result<Self, RuntimeError> {
+        value.int(interp)
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self, _interp: &Interpreter) -> Value {
+        Value::Integer(self)
+    }
+}
+
+/// Integers are converted too, like the builtin float operations do.
+impl FromValue for f64 {
+    fn from_value(value: &Value, interp: &Interpreter) -> Result<Self, RuntimeError> {
+        value.as_float(interp)
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self, _interp: &Interpreter) -> Value {
+        Value::Float(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value, interp: &Interpreter) -> Result<Self, RuntimeError> {
+        value.bool(interp)
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self, _interp: &Interpreter) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value, interp: &Interpreter) -> Result<Self, RuntimeError> {
+        Ok(value.string(interp)?.value().clone())
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self, _interp: &Interpreter) -> Value {
+        Value::String(Ptr::new(self))
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self, _interp: &Interpreter) -> Value {
+        Value::String(Ptr::new(self.to_string()))
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value, interp: &Interpreter) -> Result<Self, RuntimeError> {
+        let list = value.list(interp)?;
+        let list = list.value().to_vec();
+
+        list.iter()
+            .map(|value| T::from_value(value, interp))
+            .collect()
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self, interp: &Interpreter) -> Value {
+        Value::new_list(
+            self.into_iter()
+                .map(|value| value.into_value(interp))
+                .collect(),
+        )
+    }
+}
+
+/// `null` is `None`.
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value, interp: &Interpreter) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_value(value, interp).map(Some),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self, interp: &Interpreter) -> Value {
+        match self {
+            Some(value) => value.into_value(interp),
+            None => Value::Null,
+        }
+    }
+}
+
+/// The properties of an object.
+impl<T: FromValue> FromValue for HashMap<String, T> {
+    fn from_value(value: &Value, interp: &Interpreter) -> Result<Self, RuntimeError> {
+        let object = value.object(interp)?;
+        let properties: Vec<_> = object
+            .value()
+            .properties()
+            .iter()
+            .map(|(name, value)| (name.value().clone(), value.clone()))
+            .collect();
+
+        properties
+            .into_iter()
+            .map(|(name, value)| Ok((name, T::from_value(&value, interp)?)))
+            .collect()
+    }
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+    fn into_value(self, interp: &Interpreter) -> Value {
+        let properties = self
+            .into_iter()
+            .map(|(name, value)| (Ptr::new(name), value.into_value(interp)))
+            .collect();
+
+        Value::new_object(Object::new(
+            interp.builtins().types.object.clone(),
+            properties,
+        ))
+    }
+}
+
+/// Tuples are converted from and into lists of the same length.
+macro_rules! impl_tuple {
+    ($len:literal, $($ty:ident $name:ident),+) => {
+        impl<$($ty: FromValue),+> FromValue for ($($ty,)+) {
+            fn from_value(value: &Value, interp: &Interpreter) -> Result<Self, RuntimeError> {
+                let list = value.list(interp)?;
+                let list = list.value().to_vec();
+
+                match list.as_slice() {
+                    [$($name),+] => Ok(($($ty::from_value($name, interp)?,)+)),
+                    _ => Err(RuntimeError::new(
+                        ErrorKind::Type,
+                        format!(
+                            "expected list of {} values, but found {}",
+                            $len,
+                            list.len()
+                        ),
+                    )),
+                }
+            }
+        }
+
+        impl<$($ty: IntoValue),+> IntoValue for ($($ty,)+) {
+            fn into_value(self, interp: &Interpreter) -> Value {
+                let ($($name,)+) = self;
+                Value::new_list(vec![$($name.into_value(interp)),+])
+            }
+        }
+    };
+}
+
+impl_tuple!(1, A a);
+impl_tuple!(2, A a, B b);
+impl_tuple!(3, A a, B b, C c);
+impl_tuple!(4, A a, B b, C c, D d);
+
+/// A Rust function that can be wrapped into a native function, `Args` are
+/// its argument types. Implemented for functions of up to four arguments
+/// that convert from `Value` and return a value that converts into one,
+/// or a `Result` of such a value.
+pub trait IntoNative<Args> {
+    fn into_native(self) -> Value;
+}
+
+macro_rules! impl_into_native {
+    ($len:literal $(, $ty:ident $name:ident)*) => {
+        impl<F, R, $($ty),*> IntoNative<($($ty,)*)> for F
+        where
+            F: Fn($($ty),*) -> R + 'static,
+            R: IntoNativeResult,
+            $($ty: FromValue,)*
+        {
+            fn into_native(self) -> Value {
+                Value::native_closure(ArgPattern::Exact($len), move |interp, args| {
+                    // The arity is checked before native functions are called.
+                    let [$($name),*] = args else {
+                        unreachable!("native function called with the wrong arity")
+                    };
+
+                    self($($ty::from_value($name, interp)?),*).into_native_result(interp)
+                })
+            }
+        }
+    };
+}
+
+impl_into_native!(0);
+impl_into_native!(1, A a);
+impl_into_native!(2, A a, B b);
+impl_into_native!(3, A a, B b, C c);
+impl_into_native!(4, A a, B b, C c, D d);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Engine;
+
+    fn round_trip<T: IntoValue + FromValue>(value: T, interp: &Interpreter) -> T {
+        let value = value.into_value(interp);
+        T::from_value(&value, interp).expect("value converts back")
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut engine = Engine::new();
+        let interp = engine.interpreter();
+
+        assert_eq!(round_trip(42i64, interp), 42);
+        assert_eq!(round_trip(1.5f64, interp), 1.5);
+        assert!(round_trip(true, interp));
+        assert_eq!(round_trip("tea".to_string(), interp), "tea");
+        assert_eq!(round_trip(vec![1i64, 2, 3], interp), [1, 2, 3]);
+        assert_eq!(round_trip(Some(3i64), interp), Some(3));
+        assert_eq!(round_trip(None::<i64>, interp), None);
+        assert_eq!(
+            round_trip((1i64, "a".to_string(), false), interp),
+            (1, "a".to_string(), false)
+        );
+
+        let map = HashMap::from([("a".to_string(), 1i64), ("b".to_string(), 2)]);
+        assert_eq!(round_trip(map.clone(), interp), map);
+    }
+
+    #[test]
+    fn conversion_errors() {
+        let mut engine = Engine::new();
+        let interp = engine.interpreter();
+
+        assert_eq!(f64::from_value(&Value::Integer(2), interp).ok(), Some(2.0));
+
+        let err = String::from_value(&Value::Integer(2), interp).expect_err("fails");
+        assert_eq!(err.kind, ErrorKind::Type);
+
+        let list = vec![1i64, 2, 3].into_value(interp);
+        let err = <(i64, i64)>::from_value(&list, interp).expect_err("fails");
+        assert_eq!(err.message, "expected list of 2 values, but found 3");
+    }
+
+    #[test]
+    fn typed_native_functions() {
+        let mut engine = Engine::new();
+        engine.register_global(
+            "join",
+            Value::native_fn(|items: Vec<String>, sep: Option<String>| {
+                items.join(&sep.unwrap_or_default())
+            }),
+        );
+        engine.register_global(
+            "half",
+            Value::native_fn(|n: i64| match n % 2 {
+                0 => Ok(n / 2),
+                _ => Err(RuntimeError::new(ErrorKind::Arithmetic, "odd number")),
+            }),
+        );
+
+        let value = engine
+            .eval("join([\"a\", \"b\"], \", \")")
+            .expect("script runs");
+        assert!(matches!(value, Value::String(str) if str.value() == "a, b"));
+
+        let value = engine
+            .eval("let m = null; try { half(3); } catch e { m = e.message; } [half(4), m]")
+            .expect("script runs");
+        let result = <(i64, String)>::from_value(&value, engine.interpreter()).expect("converts");
+        assert_eq!(result, (2, "odd number".to_string()));
+
+        let value = engine
+            .eval("let t = null; try { half(\"x\"); } catch e { t = e.type; } t")
+            .expect("script runs");
+        assert!(matches!(value, Value::String(str) if str.value() == "TypeError"));
+    }
+}
@@ -0,0 +1,272 @@
+use std::fmt;
+
+use lalrpop_util::{lexer::Token, ParseError};
+
+use crate::source::LineIndex;
+
+/// A parse error as returned by `parser::Parser`.
+pub type SyntaxError<'input> = ParseError<usize, Token<'input>, LiteralError>;
+
+/// A literal that matched the grammar but has no value, e.g. an integer
+/// that doesn't fit in 64 bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiteralError {
+    pub start: usize,
+    pub end: usize,
+    pub message: &'static str,
+}
+
+impl LiteralError {
+    pub fn new(start: usize, end: usize, message: &'static str) -> Self {
+        Self {
+            start,
+            end,
+            message,
+        }
+    }
+}
+
+/// An error located in a source file, rendered with the offending line
+/// and a caret underline.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub file: String,
+    pub message: String,
+    /// 1-based line and column of the error, columns count characters.
+    pub line: usize,
+    pub column: usize,
+    /// The text of the offending line, without the line break.
+    pub source_line: String,
+    /// Number of characters to underline, at least one.
+    pub width: usize,
+    /// Readable names of the tokens that were expected instead.
+    pub expected: Vec<String>,
+}
+
+impl Diagnostic {
+    /// Create a diagnostic for the bytes `start..end` of `source`.
+    pub fn new(
+        file: &str,
+        source: &str,
+        start: usize,
+        end: usize,
+        message: impl Into<String>,
+    ) -> Self {
+        let index = LineIndex::new(source);
+        let line = index.line(start);
+        let line_start = start - (index.line_col(start).1 - 1);
+        let line_end = source[line_start..]
+            .find('\n')
+            .map_or(source.len(), |i| line_start + i);
+        let source_line = source[line_start..line_end].trim_end_matches('\r');
+
+        let column = source[line_start..start].chars().count() + 1;
+        let width = source[start..end.clamp(start, line_end)].chars().count();
+
+        Self {
+            file: file.to_string(),
+            message: message.into(),
+            line,
+            column,
+            source_line: source_line.to_string(),
+            width: width.max(1),
+            expected: Vec::new(),
+        }
+    }
+
+    /// Create a diagnostic for an error returned by the parser.
+    pub fn from_parse_error(file: &str, source: &str, error: &SyntaxError<'_>) -> Self {
+        match error {
+            ParseError::InvalidToken { location } => {
+                let end = source[*location..]
+                    .chars()
+                    .next()
+                    .map_or(*location, |c| location + c.len_utf8());
+                Self::new(file, source, *location, end, "invalid token")
+            }
+            ParseError::UnrecognizedEOF { location, expected } => {
+                Self::new(file, source, *location, *location, "unexpected end of file")
+                    .with_expected(expected)
+            }
+            ParseError::UnrecognizedToken {
+                token: (start, token, end),
+                expected,
+            } => Self::new(
+                file,
+                source,
+                *start,
+                *end,
+                format!("unexpected token `{}`", token),
+            )
+            .with_expected(expected),
+            ParseError::ExtraToken {
+                token: (start, token, end),
+            } => Self::new(
+                file,
+                source,
+                *start,
+                *end,
+                format!("extra token `{}`", token),
+            ),
+            ParseError::User { error } => {
+                Self::new(file, source, error.start, error.end, error.message)
+            }
+        }
+    }
+
+    fn with_expected(mut self, expected: &[String]) -> Self {
+        self.expected = expected.iter().map(|t| token_name(t)).collect();
+        self
+    }
+}
+
+/// Turn a terminal name used by the grammar into a readable name.
+fn token_name(terminal: &str) -> String {
+    if let Some(pattern) = terminal
+        .strip_prefix(r##"r#""##)
+        .and_then(|t| t.strip_suffix(r##""#"##))
+    {
+        let name = match pattern {
+            r"[a-zA-Z]([a-zA-Z0-9]|_)*" => "identifier",
+            r"[0-9]+" => "integer",
+            r"(([1-9][0-9]*)|0)\\.[0-9]+" => "float",
+            r#"\"[^\"]*\""# => "string",
+            _ => pattern,
+        };
+
+        return name.to_string();
+    }
+
+    match terminal.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+        Some(literal) => format!("`{}`", literal.replace(r#"\""#, "\"")),
+        None => terminal.to_string(),
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        // Keep tabs so the caret lines up with the source line.
+        let indent: String = self
+            .source_line
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        writeln!(f, "error: {}", self.message)?;
+        writeln!(
+            f,
+            "{}--> {}:{}:{}",
+            gutter, self.file, self.line, self.column
+        )?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.source_line)?;
+        write!(f, "{} | {}{}", gutter, indent, "^".repeat(self.width))?;
+
+        match self.expected.as_slice() {
+            [] => Ok(()),
+            [token] => write!(f, "\n{} = expected {}", gutter, token),
+            tokens => write!(f, "\n{} = expected one of {}", gutter, tokens.join(", ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn parse_error(source: &str) -> Diagnostic {
+        let err = Parser::new()
+            .parse("test.san", source)
+            .expect_err("source doesn't parse");
+        Diagnostic::from_parse_error("test.san", source, &err)
+    }
+
+    #[test]
+    fn literals_out_of_range() {
+        let diagnostic = parse_error("let x = 9223372036854775808;");
+        assert_eq!(diagnostic.message, "integer literal is too large");
+        assert_eq!(
+            (diagnostic.line, diagnostic.column, diagnostic.width),
+            (1, 9, 19)
+        );
+    }
+
+    #[test]
+    fn unexpected_token() {
+        let diagnostic = parse_error("let = 1;");
+        assert_eq!(diagnostic.message, "unexpected token `=`");
+        assert!(diagnostic.expected.contains(&"identifier".to_string()));
+
+        let diagnostic = parse_error("let x = (1;");
+        assert_eq!(diagnostic.message, "unexpected token `;`");
+        assert_eq!((diagnostic.line, diagnostic.column), (1, 11));
+    }
+
+    #[test]
+    fn unexpected_end_of_file() {
+        let diagnostic = parse_error("let x = 1;\nlet y =");
+        assert_eq!(diagnostic.message, "unexpected end of file");
+        assert_eq!(
+            (diagnostic.line, diagnostic.column, diagnostic.width),
+            (2, 8, 1)
+        );
+    }
+
+    #[test]
+    fn render() {
+        let diagnostic = parse_error("let x = 1;\nlet = 2;");
+
+        assert_eq!(
+            diagnostic.to_string(),
+            "error: unexpected token `=`
+ --> test.san:2:5
+  |
+2 | let = 2;
+  |     ^
+  = expected identifier"
+        );
+    }
+
+    #[test]
+    fn render_tabs_and_multi_byte_characters() {
+        // Columns count characters, and the tab is kept so the underline
+        // lines up with the source line however wide tabs are shown.
+        let diagnostic = parse_error("\tlet s = \"é\"; let x = 99999999999999999999;");
+        assert_eq!((diagnostic.column, diagnostic.width), (23, 20));
+
+        assert_eq!(
+            diagnostic.to_string(),
+            format!(
+                "error: integer literal is too large
+ --> test.san:1:23
+  |
+1 | \tlet s = \"é\"; let x = 99999999999999999999;
+  | \t{}{}",
+                " ".repeat(21),
+                "^".repeat(20)
+            )
+        );
+    }
+
+    #[test]
+    fn render_many_expected_tokens() {
+        let diagnostic = Diagnostic {
+            expected: vec!["`;`".to_string(), "`+`".to_string()],
+            ..Diagnostic::new("test.san", "let x = 1", 9, 9, "unexpected end of file")
+        };
+
+        assert!(diagnostic
+            .to_string()
+            .ends_with("\n  = expected one of `;`, `+`"));
+    }
+
+    #[test]
+    fn negative_integer_literals() {
+        let diagnostic = parse_error("let x = -9223372036854775809;");
+        assert_eq!(diagnostic.message, "integer literal is too large");
+        assert_eq!(diagnostic.column, 10);
+    }
+}
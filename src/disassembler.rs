@@ -0,0 +1,188 @@
+use std::fmt::{self, Write};
+
+use crate::{frame::Code, instruction::Instruction, value::Value};
+
+/// Render `code` and the code of every function defined in it as a listing
+/// of instructions with their operands resolved.
+pub fn disassemble(code: &Code) -> String {
+    let mut out = String::new();
+    write_code(&mut out, code).expect("writing to a string can't fail");
+    out
+}
+
+fn write_code(out: &mut String, code: &Code) -> fmt::Result {
+    let parameters: Vec<&str> = code.locals()[..code.parameters()]
+        .iter()
+        .map(|name| name.value().as_str())
+        .collect();
+    writeln!(
+        out,
+        "== {}({}) in {} ==",
+        code.name(),
+        parameters.join(", "),
+        code.file()
+    )?;
+
+    let mut last_line = None;
+
+    for (index, instruction) in code.instructions().iter().enumerate() {
+        let line = code.line(index);
+
+        // Like the line table, only show a line when it changes.
+        let line_column = match line {
+            Some(line) if last_line != Some(line) => line.to_string(),
+            _ => String::new(),
+        };
+        last_line = line.or(last_line);
+
+        let operand = operand(code, *instruction);
+        let instruction = format!("{:?}", instruction);
+
+        if let Some(operand) = operand {
+            writeln!(
+                out,
+                "{:>5} {:>5} {:<24} ; {}",
+                line_column, index, instruction, operand
+            )?;
+        } else {
+            writeln!(out, "{:>5} {:>5} {}", line_column, index, instruction)?;
+        }
+    }
+
+    if !code.handlers().is_empty() {
+        writeln!(out, "handlers:")?;
+
+        for handler in code.handlers() {
+            writeln!(
+                out,
+                "  {}..{} -> {} (depth {})",
+                handler.start, handler.end, handler.target, handler.depth
+            )?;
+        }
+    }
+
+    for constant in code.constants() {
+        if let Value::Code(code) = constant {
+            writeln!(out)?;
+            write_code(out, code.value())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The operand of `instruction` decoded against the constants, names,
+/// locals and cells of `code`.
+fn operand(code: &Code, instruction: Instruction) -> Option<String> {
+    let name = |namei: usize| match code.names().get(namei) {
+        Some(name) => name.value().clone(),
+        None => "<invalid name>".to_string(),
+    };
+    let local = |slot: usize| match code.locals().get(slot) {
+        Some(local) => local.value().clone(),
+        None => "<invalid local>".to_string(),
+    };
+    let cell = |index: usize| match code.cells().get(index) {
+        Some(cell) => cell.value().clone(),
+        None => "<invalid cell>".to_string(),
+    };
+
+    match instruction {
+        Instruction::LoadConstant(consi) => Some(match code.constants().get(consi) {
+            Some(constant) => constant_repr(constant),
+            None => "<invalid constant>".to_string(),
+        }),
+        Instruction::Declare(namei)
+        | Instruction::LoadProperty(namei)
+        | Instruction::StoreProperty(namei)
+        | Instruction::LoadGlobal(namei)
+        | Instruction::StoreGlobal(namei) => Some(name(namei)),
+        Instruction::LoadLocal(slot) | Instruction::StoreLocal(slot) => Some(local(slot)),
+        Instruction::LoadCell(index) | Instruction::StoreCell(index) => Some(cell(index)),
+        Instruction::Jump(target)
+        | Instruction::JumpFalse(target)
+        | Instruction::JumpFalseOrPop(target)
+        | Instruction::JumpTrueOrPop(target)
+        | Instruction::ForIter(target) => Some(format!("to {}", target)),
+        _ => None,
+    }
+}
+
+/// A short representation of a constant, constants are only ever
+/// literals and function bodies.
+fn constant_repr(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Integer(int) => int.to_string(),
+        Value::Float(float) => format!("{:?}", float),
+        Value::String(str) => format!("{:?}", str.value()),
+        Value::Code(code) => format!("<code {}>", code.value().name()),
+        _ => "<value>".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::Parser, CodeBuilder, OptimizationLevel};
+
+    fn compile(source: &str) -> Code {
+        let module = Parser::new()
+            .parse("test.san", source)
+            .expect("valid syntax");
+        let mut code_builder = CodeBuilder::new(0);
+        code_builder.set_optimization(OptimizationLevel::None);
+        code_builder
+            .compile_module(&module, source)
+            .expect("valid program");
+        code_builder.build()
+    }
+
+    #[test]
+    fn listing() {
+        let code = compile(
+            "let greet = fn(name) {
+    let n = 1.5;
+    try { print(\"hi \" + name); } catch e { ^ n; }
+};
+greet(\"x\");
+",
+        );
+
+        // Lines are only shown when they change, operands are resolved
+        // after the `;` and functions follow the code defining them.
+        assert_eq!(
+            disassemble(&code),
+            r#"== <module>() in test.san ==
+    1     0 LoadConstant(0)          ; <code greet>
+          1 CreateFunction
+          2 Declare(0)               ; greet
+    5     3 LoadConstant(1)          ; "x"
+          4 LoadGlobal(0)            ; greet
+          5 Call(1)
+          6 Pop
+          7 Exit(0)
+
+== greet(name) in test.san ==
+    2     0 LoadConstant(0)          ; 1.5
+          1 StoreLocal(1)            ; n
+    3     2 LoadLocal(0)             ; name
+          3 LoadConstant(1)          ; "hi "
+          4 Add
+          5 LoadGlobal(0)            ; print
+          6 Call(1)
+          7 Pop
+          8 Jump(13)                 ; to 13
+          9 StoreLocal(2)            ; e
+         10 LoadLocal(1)             ; n
+         11 Return
+         12 Jump(13)                 ; to 13
+    4    13 LoadConstant(2)          ; null
+         14 Return
+handlers:
+  2..8 -> 9 (depth 0)
+"#
+        );
+    }
+}
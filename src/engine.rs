@@ -0,0 +1,474 @@
+use std::{fmt, fs, io};
+
+use crate::{
+    ast::Module,
+    builtins::Builtins,
+    bytecode::{self, BytecodeError},
+    diagnostic::{Diagnostic, SyntaxError},
+    error::{ErrorKind, RuntimeError},
+    frame::{Code, Frame},
+    parser::{EvalParser, Parser},
+    ptr::{Ptr, PtrMut},
+    value::Value,
+    CodeBuilder, Interpreter, OptimizationLevel,
+};
+
+/// File name of the code run by `Engine::eval`.
+const EVAL_FILE: &str = "<eval>";
+
+/// Runs scripts for a host application. Every script runs in the same
+/// root frame, so module variables declared by one stay in scope for the
+/// scripts run after it and for `Engine::call`.
+pub struct Engine {
+    frame: PtrMut<Frame>,
+    interpreter: Interpreter,
+    optimization: OptimizationLevel,
+}
+
+/// An error returned by an `Engine`.
+#[derive(Debug)]
+pub enum EngineError {
+    /// A script file couldn't be read.
+    Io {
+        path: String,
+        error: io::Error,
+    },
+    /// A script file is neither bytecode nor utf-8 text.
+    InvalidUtf8 {
+        path: String,
+    },
+    Syntax(Diagnostic),
+    Bytecode(BytecodeError),
+    Runtime(RuntimeError),
+    /// The script called `exit(code)`, the host decides what to do.
+    ExitRequested(i32),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "error while reading `{}`: {}", path, error),
+            Self::InvalidUtf8 { path } => write!(f, "`{}` is not valid utf-8", path),
+            Self::Syntax(diagnostic) => diagnostic.fmt(f),
+            Self::Bytecode(err) => write!(f, "error while loading bytecode: {}", err),
+            // Keep the alternate form, which includes the traceback.
+            Self::Runtime(err) => fmt::Display::fmt(err, f),
+            Self::ExitRequested(code) => write!(f, "exit requested with code {}", code),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<RuntimeError> for EngineError {
+    fn from(err: RuntimeError) -> Self {
+        match err.exit_code() {
+            Some(code) => Self::ExitRequested(code),
+            None => Self::Runtime(err),
+        }
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        let code = compile(
+            &Module {
+                name: EVAL_FILE.to_string(),
+                body: Vec::new(),
+            },
+            "",
+            OptimizationLevel::default(),
+        )
+        .expect("an empty module compiles");
+
+        let frame = PtrMut::new(Frame::new(Ptr::new(code), None));
+        let interpreter = Interpreter::new(frame.clone(), Builtins::new());
+
+        Self {
+            frame,
+            interpreter,
+            optimization: OptimizationLevel::default(),
+        }
+    }
+
+    /// The interpreter running the scripts, e.g. to set its limits.
+    pub fn interpreter(&mut self) -> &mut Interpreter {
+        &mut self.interpreter
+    }
+
+    /// Set how much the scripts run after this are optimized.
+    pub fn set_optimization(&mut self, optimization: OptimizationLevel) {
+        self.optimization = optimization;
+    }
+
+    /// Declare a module variable, see `Interpreter::register_global`.
+    pub fn register_global(&mut self, name: &str, value: Value) {
+        self.interpreter.register_global(name, value);
+    }
+
+    /// The module variable or builtin named `name`.
+    pub fn global(&self, name: &str) -> Option<Value> {
+        self.interpreter.global(name)
+    }
+
+    /// Run `source`, returns the value of its last statement if that is an
+    /// expression and `null` otherwise. The `;` after the last statement
+    /// may be left out, like in the REPL.
+    pub fn eval(&mut self, source: &str) -> Result<Value, EngineError> {
+        let module = EvalParser::new()
+            .parse(EVAL_FILE, source)
+            .map_err(|err| syntax_error(EVAL_FILE, source, &err))?;
+        let code = compile(&module, source, self.optimization).map_err(EngineError::Syntax)?;
+
+        self.run(code)
+    }
+
+    /// Run a script or a compiled bytecode file, see `Engine::eval`.
+    pub fn run_file(&mut self, path: &str) -> Result<Value, EngineError> {
+        let code = self.load_file(path)?;
+        self.run(code)
+    }
+
+    /// Read a script or a compiled bytecode file and load it without
+    /// running it, see `Engine::load`.
+    pub fn load_file(&self, path: &str) -> Result<Code, EngineError> {
+        let bytes = fs::read(path).map_err(|error| EngineError::Io {
+            path: path.to_string(),
+            error,
+        })?;
+
+        self.load(path, &bytes)
+    }
+
+    /// Compile a script or load compiled bytecode, e.g. to serialize or
+    /// disassemble it. `name` is the file shown in errors and tracebacks.
+    pub fn load(&self, name: &str, bytes: &[u8]) -> Result<Code, EngineError> {
+        if bytecode::is_bytecode(bytes) {
+            return bytecode::deserialize(bytes).map_err(EngineError::Bytecode);
+        }
+
+        let source = std::str::from_utf8(bytes).map_err(|_| EngineError::InvalidUtf8 {
+            path: name.to_string(),
+        })?;
+        let module = Parser::new()
+            .parse(name, source)
+            .map_err(|err| syntax_error(name, source, &err))?;
+
+        compile(&module, source, self.optimization).map_err(EngineError::Syntax)
+    }
+
+    /// Run code returned by `Engine::load`, see `Engine::eval`.
+    pub fn run(&mut self, code: Code) -> Result<Value, EngineError> {
+        self.frame.value_mut().load_code(Ptr::new(code));
+        Ok(self.interpreter.run()?)
+    }
+
+    /// Call the module variable or builtin named `name` with `args`.
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, EngineError> {
+        let value = self.global(name).ok_or_else(|| {
+            RuntimeError::new(
+                ErrorKind::Name,
+                format!("couldn't resolve variable named `{}`", name),
+            )
+        })?;
+
+        Ok(self.interpreter.call_with_return(value, args)?)
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn syntax_error(file: &str, source: &str, err: &SyntaxError<'_>) -> EngineError {
+    EngineError::Syntax(Diagnostic::from_parse_error(file, source, err))
+}
+
+fn compile(
+    module: &Module,
+    source: &str,
+    optimization: OptimizationLevel,
+) -> Result<Code, Diagnostic> {
+    let mut code_builder = CodeBuilder::new(0);
+    code_builder.set_optimization(optimization);
+    code_builder.compile_eval(module, source)?;
+    Ok(code_builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        rc::Rc,
+    };
+
+    use super::*;
+    use crate::{
+        frame::{DebugInfo, Variables},
+        instruction::Instruction,
+        value::ArgPattern,
+    };
+
+    #[test]
+    fn eval_keeps_module_variables() {
+        let mut engine = Engine::new();
+        engine.eval("let x = 20;").expect("script runs");
+
+        // The `;` after the last statement may be left out.
+        let value = engine.eval("x + 1").expect("script runs");
+        assert!(matches!(value, Value::Integer(21)));
+        assert!(matches!(engine.global("x"), Some(Value::Integer(20))));
+        assert!(matches!(engine.eval("let y = 1;"), Ok(Value::Null)));
+    }
+
+    #[test]
+    fn last_semicolon_is_optional() {
+        let mut engine = Engine::new();
+
+        assert!(matches!(engine.eval("let a = 1"), Ok(Value::Null)));
+        assert!(matches!(engine.eval("a = a + 1"), Ok(Value::Null)));
+        assert!(matches!(engine.eval("a;"), Ok(Value::Integer(2))));
+
+        // Only the last `;` may be left out, and an error is reported where
+        // it is, not where a retry with a `;` added fails.
+        match engine.eval("let b = 1 let c = 2") {
+            Err(EngineError::Syntax(diagnostic)) => assert_eq!(diagnostic.column, 11),
+            _ => panic!("expected a syntax error"),
+        }
+        match engine.eval("let = 1; 2") {
+            Err(EngineError::Syntax(diagnostic)) => {
+                assert_eq!(diagnostic.message, "unexpected token `=`")
+            }
+            _ => panic!("expected a syntax error"),
+        }
+    }
+
+    #[test]
+    fn call_script_function() {
+        let mut engine = Engine::new();
+        engine
+            .eval("let greet = fn(name) \"hello \" + name;")
+            .expect("script runs");
+
+        let value = engine
+            .call("greet", &[Value::String(Ptr::new("world".to_string()))])
+            .expect("call succeeds");
+        assert!(matches!(value, Value::String(str) if str.value() == "hello world"));
+
+        match engine.call("missing", &[]) {
+            Err(EngineError::Runtime(err)) => assert_eq!(err.kind, ErrorKind::Name),
+            _ => panic!("expected a NameError"),
+        }
+    }
+
+    #[test]
+    fn register_global_closure() {
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+
+        let mut engine = Engine::new();
+        engine.register_global(
+            "tick",
+            Value::native_closure(ArgPattern::Exact(0), move |_, _| {
+                counter.set(counter.get() + 1);
+                Ok(Value::Integer(counter.get()))
+            }),
+        );
+
+        let value = engine.eval("tick(); tick(); tick()").expect("script runs");
+        assert!(matches!(value, Value::Integer(3)));
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn closure_state_across_calls() {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+
+        let mut engine = Engine::new();
+        engine.register_global(
+            "log",
+            Value::native_closure(ArgPattern::Exact(1), move |interp, args| {
+                log.borrow_mut().push(args[0].int(interp)?);
+                Ok(Value::Integer(log.borrow().iter().sum()))
+            }),
+        );
+
+        // The state is kept between calls and between runs.
+        engine.eval("log(1); log(2);").expect("script runs");
+        let value = engine.eval("let f = fn(n) log(n * 10); f(3)");
+        assert!(matches!(value, Ok(Value::Integer(33))));
+        assert_eq!(*seen.borrow(), [1, 2, 30]);
+
+        // The host calls the same closure as the script.
+        let value = engine.call("log", &[Value::Integer(4)]);
+        assert!(matches!(value, Ok(Value::Integer(37))));
+    }
+
+    #[test]
+    fn closure_calls_back_into_the_script() {
+        let mut engine = Engine::new();
+        engine.register_global(
+            "twice",
+            Value::native_closure(ArgPattern::Exact(2), |interp, args| {
+                let once = interp.call_with_return(args[0].clone(), &args[1..])?;
+                interp.call_with_return(args[0].clone(), &[once])
+            }),
+        );
+
+        let value = engine.eval("twice(fn(n) n * 3, 2)");
+        assert!(matches!(value, Ok(Value::Integer(18))));
+    }
+
+    #[test]
+    fn closure_errors() {
+        let mut engine = Engine::new();
+        engine.register_global(
+            "check",
+            Value::native_closure(ArgPattern::Exact(1), |interp, args| {
+                match args[0].int(interp)? {
+                    n if n < 0 => Err(RuntimeError::new(ErrorKind::Error, "negative")),
+                    n => Ok(Value::Integer(n)),
+                }
+            }),
+        );
+
+        // Errors of the closure can be caught by the script.
+        let value = engine.eval("let m = null; try { check(-1); } catch e { m = e.message; } m");
+        assert!(matches!(value, Ok(Value::String(m)) if m.value() == "negative"));
+
+        // Uncaught, they reach the host with a traceback.
+        match engine.eval("let f = fn() check(\"x\");\nf();") {
+            Err(EngineError::Runtime(err)) => {
+                assert_eq!(err.kind, ErrorKind::Type);
+                assert_eq!(err.traceback.len(), 2);
+                assert_eq!(err.traceback[0].function, "f");
+            }
+            _ => panic!("expected a TypeError"),
+        }
+
+        match engine.eval("check(1, 2);") {
+            Err(EngineError::Runtime(err)) => assert_eq!(err.kind, ErrorKind::Arity),
+            _ => panic!("expected an ArityError"),
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let mut engine = Engine::new();
+
+        assert!(matches!(
+            engine.eval("let = ;"),
+            Err(EngineError::Syntax(_))
+        ));
+        assert!(matches!(engine.eval("break;"), Err(EngineError::Syntax(_))));
+        assert!(matches!(
+            engine.eval("throw \"x\";"),
+            Err(EngineError::Runtime(_))
+        ));
+        assert!(matches!(
+            engine.run_file("/nonexistent/script.san"),
+            Err(EngineError::Io { .. })
+        ));
+
+        // `exit` doesn't end the process, and the engine stays usable.
+        assert!(matches!(
+            engine.eval("exit(3);"),
+            Err(EngineError::ExitRequested(3))
+        ));
+        assert!(matches!(engine.eval("1 + 1"), Ok(Value::Integer(2))));
+    }
+
+    #[test]
+    fn exit_codes() {
+        let mut engine = Engine::new();
+
+        assert!(matches!(
+            engine.eval("exit();"),
+            Err(EngineError::ExitRequested(0))
+        ));
+        assert!(matches!(
+            engine.eval("exit(-2147483648);"),
+            Err(EngineError::ExitRequested(i32::MIN))
+        ));
+
+        // Codes are not truncated to 32 bits.
+        match engine.eval("exit(4294967297);") {
+            Err(EngineError::Runtime(err)) => {
+                assert_eq!(err.kind, ErrorKind::Value);
+                assert_eq!(err.message, "exit code 4294967297 doesn't fit in 32 bits");
+            }
+            _ => panic!("expected a ValueError"),
+        }
+
+        // So are the codes of `Exit` instructions in loaded bytecode.
+        let exit = |code| {
+            Code::new(
+                vec![Instruction::Exit(code)],
+                Vec::new(),
+                Vec::new(),
+                Variables::default(),
+                Vec::new(),
+                DebugInfo::default(),
+            )
+            .expect("valid code")
+        };
+        assert!(matches!(
+            engine.run(exit(3)),
+            Err(EngineError::ExitRequested(3))
+        ));
+        match engine.run(exit(4294967297)) {
+            Err(EngineError::Runtime(err)) => {
+                assert_eq!(err.kind, ErrorKind::Value);
+                assert_eq!(err.message, "exit code 4294967297 doesn't fit in 32 bits");
+            }
+            _ => panic!("expected a ValueError"),
+        }
+    }
+
+    #[test]
+    fn exit_is_not_catchable() {
+        let mut engine = Engine::new();
+
+        let result = engine.eval(
+            "let ran = [];
+            try { exit(5); } catch e { ran.push(\"catch\"); } finally { ran.push(\"finally\"); }",
+        );
+        assert!(matches!(result, Err(EngineError::ExitRequested(5))));
+        assert!(matches!(engine.eval("ran"), Ok(Value::List(ran)) if ran.value().is_empty()));
+    }
+
+    #[test]
+    fn run_source_and_bytecode_files() {
+        let dir = std::env::temp_dir().join(format!("san-script-engine-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("temporary directory");
+        let source_path = dir.join("script.san");
+        let bytecode_path = dir.join("script.sanc");
+
+        let source = "let double = fn(n) n * 2;\n";
+        fs::write(&source_path, source).expect("write script");
+        let module = Parser::new()
+            .parse("script.san", source)
+            .expect("valid syntax");
+        let code = compile(&module, source, OptimizationLevel::default()).expect("compiles");
+        fs::write(
+            &bytecode_path,
+            bytecode::serialize(&code).expect("serializable"),
+        )
+        .expect("write bytecode");
+
+        for path in [&source_path, &bytecode_path] {
+            let mut engine = Engine::new();
+            engine
+                .run_file(path.to_str().expect("utf-8 path"))
+                .expect("script runs");
+
+            let value = engine.call("double", &[Value::Integer(4)]);
+            assert!(matches!(value, Ok(Value::Integer(8))));
+        }
+
+        fs::remove_dir_all(&dir).expect("remove temporary directory");
+    }
+}
@@ -0,0 +1,251 @@
+use std::fmt;
+
+use crate::{instruction::Instruction, value::Value};
+
+/// The category of a runtime error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A value of the wrong type was used, or a type doesn't support an operation.
+    Type,
+    /// A function was called with the wrong number of arguments.
+    Arity,
+    /// A subscript was out of bounds.
+    Index,
+    /// A variable could not be resolved.
+    Name,
+    /// An object has no property with the requested name.
+    Property,
+    /// Division by zero or an arithmetic overflow.
+    Arithmetic,
+    /// A value has the right type but is out of range, like an exit code
+    /// that doesn't fit in 32 bits.
+    Value,
+    /// A host value was used while a native function borrows it.
+    Borrow,
+    /// Calls were nested deeper than the interpreter allows.
+    Recursion,
+    /// An error thrown by a script.
+    Error,
+    /// The instruction budget of the interpreter ran out.
+    Budget,
+    /// The deadline of the interpreter passed.
+    Timeout,
+    /// The script called `exit`, see `RuntimeError::exit_code`.
+    Exit,
+}
+
+impl ErrorKind {
+    /// The name of this kind as shown to users.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Type => "TypeError",
+            Self::Arity => "ArityError",
+            Self::Index => "IndexError",
+            Self::Name => "NameError",
+            Self::Property => "PropertyError",
+            Self::Arithmetic => "ArithmeticError",
+            Self::Value => "ValueError",
+            Self::Borrow => "BorrowError",
+            Self::Recursion => "RecursionError",
+            Self::Error => "Error",
+            Self::Budget => "BudgetError",
+            Self::Timeout => "TimeoutError",
+            Self::Exit => "Exit",
+        }
+    }
+
+    /// Whether this kind stops the interpreter instead of being handled
+    /// by the script.
+    pub fn is_limit(&self) -> bool {
+        matches!(self, Self::Budget | Self::Timeout)
+    }
+
+    /// Whether scripts can catch errors of this kind. The limits and
+    /// `exit` always end the run.
+    pub fn is_catchable(&self) -> bool {
+        !self.is_limit() && *self != Self::Exit
+    }
+
+    /// Find the kind with the given name. Scripts can't throw the limit
+    /// kinds or `Exit`, so they are not found.
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Self::Type,
+            Self::Arity,
+            Self::Index,
+            Self::Name,
+            Self::Property,
+            Self::Arithmetic,
+            Self::Value,
+            Self::Borrow,
+            Self::Recursion,
+            Self::Error,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == name)
+    }
+}
+
+/// A frame that an error was propagated through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub function: String,
+    pub file: String,
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "File \"{}\"", self.file)?;
+
+        if let Some(line) = self.line {
+            write!(f, ", line {}", line)?;
+        }
+
+        write!(f, ", in {}", self.function)
+    }
+}
+
+/// An error raised while running a script.
+#[derive(Clone)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// The failing instruction and its index in the running code.
+    /// `None` if the error was not raised by an instruction.
+    pub instruction: Option<(usize, Instruction)>,
+    /// The value thrown by the script, `None` for errors raised natively.
+    pub value: Option<Value>,
+    /// The frames the error was propagated through, innermost first.
+    pub traceback: Vec<TraceEntry>,
+}
+
+impl RuntimeError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            instruction: None,
+            value: None,
+            traceback: Vec::new(),
+        }
+    }
+
+    /// Create the error ending a run whose script called `exit(code)`.
+    pub fn exit(code: i32) -> Self {
+        Self {
+            value: Some(Value::Integer(code as i64)),
+            ..Self::new(
+                ErrorKind::Exit,
+                format!("exit requested with code {}", code),
+            )
+        }
+    }
+
+    /// The exit code if the script called `exit`, the host decides
+    /// whether to end the process.
+    pub fn exit_code(&self) -> Option<i32> {
+        match (self.kind, &self.value) {
+            (ErrorKind::Exit, Some(Value::Integer(code))) => i32::try_from(*code).ok(),
+            _ => None,
+        }
+    }
+
+    /// The source line of the failing instruction, if known.
+    pub fn line(&self) -> Option<usize> {
+        self.traceback.first().and_then(|entry| entry.line)
+    }
+}
+
+impl fmt::Debug for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RuntimeError")
+            .field("kind", &self.kind)
+            .field("message", &self.message)
+            .field("instruction", &self.instruction)
+            .field("traceback", &self.traceback)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Identical entries following each other are printed this many times,
+/// the rest are counted, like deep recursion in Python tracebacks.
+const MAX_REPEATED_ENTRIES: usize = 3;
+
+/// The alternate form (`{:#}`) prints the traceback before the error,
+/// most recent call last.
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() && !self.traceback.is_empty() {
+            writeln!(f, "Traceback (most recent call last):")?;
+
+            let mut entries = self.traceback.iter().rev().peekable();
+            while let Some(entry) = entries.next() {
+                let mut repeated = 1;
+                while entries.next_if_eq(&entry).is_some() {
+                    repeated += 1;
+                }
+
+                for _ in 0..repeated.min(MAX_REPEATED_ENTRIES) {
+                    writeln!(f, "  {}", entry)?;
+                }
+
+                if repeated > MAX_REPEATED_ENTRIES {
+                    let more = repeated - MAX_REPEATED_ENTRIES;
+                    let times = if more == 1 { "time" } else { "times" };
+                    writeln!(f, "  [Previous line repeated {} more {}]", more, times)?;
+                }
+            }
+        }
+
+        write!(f, "{}: {}", self.kind.name(), self.message)?;
+
+        if let Some((index, instruction)) = self.instruction {
+            write!(f, " (at instruction {} `{:?}`", index, instruction)?;
+
+            if let Some(line) = self.line() {
+                write!(f, ", line {}", line)?;
+            }
+
+            write!(f, ")")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(function: &str, line: usize) -> TraceEntry {
+        TraceEntry {
+            function: function.to_string(),
+            file: "test.san".to_string(),
+            line: Some(line),
+        }
+    }
+
+    #[test]
+    fn collapses_repeated_traceback_entries() {
+        let mut err = RuntimeError::new(ErrorKind::Recursion, "maximum recursion depth exceeded");
+        err.traceback = vec![entry("g", 2); 2];
+        err.traceback.extend(vec![entry("f", 1); 10]);
+        err.traceback.push(entry("<module>", 3));
+
+        assert_eq!(
+            format!("{:#}", err),
+            "Traceback (most recent call last):
+  File \"test.san\", line 3, in <module>
+  File \"test.san\", line 1, in f
+  File \"test.san\", line 1, in f
+  File \"test.san\", line 1, in f
+  [Previous line repeated 7 more times]
+  File \"test.san\", line 2, in g
+  File \"test.san\", line 2, in g
+RecursionError: maximum recursion depth exceeded"
+        );
+    }
+}
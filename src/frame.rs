@@ -1,93 +1,269 @@
 use std::collections::HashMap;
 
+use crate::gc;
+use crate::instruction::CellIndex;
 use crate::instruction::ConstantIndex;
 use crate::instruction::Instruction;
 use crate::instruction::NameIndex;
+use crate::instruction::SlotIndex;
 use crate::ptr::{Ptr, PtrMut};
 use crate::value::Value;
+use crate::verifier::{self, VerifyError};
+
+/// A variable shared between a frame and the functions it creates. It
+/// holds `None` until the `let` declaring it runs.
+pub type Cell = PtrMut<Option<Value>>;
 
 pub struct Function {
     code: Ptr<Code>,
-    outer_frame: PtrMut<Frame>,
+    /// The cells captured from the defining frame, see `Code::captures`.
+    cells: Vec<Cell>,
 }
 
 impl Function {
-    pub fn new(code: Ptr<Code>, outer_frame: PtrMut<Frame>) -> Self {
-        Self { code, outer_frame }
+    pub fn new(code: Ptr<Code>, cells: Vec<Cell>) -> Self {
+        Self { code, cells }
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
     }
 
     pub fn as_frame(&self, calling_frame: PtrMut<Frame>) -> Frame {
-        Frame::new(
-            self.code.clone(),
-            Some(calling_frame),
-            Some(self.outer_frame.clone()),
-        )
+        let mut frame = Frame::new(self.code.clone(), Some(calling_frame));
+        frame.cells.extend(self.cells.iter().cloned());
+        frame
     }
 }
 
+/// An entry in the exception handler table of a `Code` object.
+/// Errors raised by instructions in `start..end` continue at `target`
+/// with the stack truncated to `depth` and the error value pushed.
+#[derive(Debug, Clone, Copy)]
+pub struct Handler {
+    pub start: usize,
+    pub end: usize,
+    pub target: usize,
+    pub depth: usize,
+}
+
+/// Source information of a `Code` object, used for error reporting.
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo {
+    /// Name of the function, `<module>` for module level code.
+    pub name: String,
+    /// Name of the module the code was compiled from.
+    pub file: String,
+    /// `(first instruction, line)` pairs sorted by instruction.
+    pub lines: Vec<(usize, usize)>,
+}
+
+/// The variables of a `Code` object, resolved by the compiler.
+#[derive(Clone, Default)]
+pub struct Variables {
+    /// Names of the local slots, the first `parameters` hold the arguments.
+    pub locals: Vec<Ptr<String>>,
+    pub parameters: usize,
+    /// Names of the cells shared with nested functions. The cells created
+    /// by each frame come first, then the ones captured by the function.
+    pub cells: Vec<Ptr<String>>,
+    /// For every captured cell, the index of the cell in the frame that
+    /// creates the function.
+    pub captures: Vec<usize>,
+}
+
 pub struct Code {
     instructions: Vec<Instruction>,
     constants: Vec<Value>,
     names: Vec<Ptr<String>>,
-    parameters: usize,
+    variables: Variables,
+    handlers: Vec<Handler>,
+    debug: DebugInfo,
+    /// Maximum depth of the stack, found by the verifier.
+    max_stack: usize,
 }
 
 impl Code {
+    /// Create a code object, it is verified so it can't make the
+    /// interpreter panic, see `verifier::verify`.
     pub fn new(
         instructions: Vec<Instruction>,
         constants: Vec<Value>,
         names: Vec<Ptr<String>>,
-        parameters: usize,
-    ) -> Self {
-        Self {
+        variables: Variables,
+        handlers: Vec<Handler>,
+        debug: DebugInfo,
+    ) -> Result<Self, VerifyError> {
+        let mut code = Self {
             instructions,
             constants,
             names,
-            parameters,
-        }
+            variables,
+            handlers,
+            debug,
+            max_stack: 0,
+        };
+
+        code.max_stack = verifier::verify(&code)?;
+        Ok(code)
+    }
+
+    pub fn max_stack(&self) -> usize {
+        self.max_stack
+    }
+
+    /// Find the innermost handler covering the instruction at `index`.
+    pub fn handler(&self, index: usize) -> Option<Handler> {
+        self.handlers
+            .iter()
+            .find(|h| h.start <= index && index < h.end)
+            .copied()
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    pub fn constants(&self) -> &[Value] {
+        &self.constants
+    }
+
+    pub fn names(&self) -> &[Ptr<String>] {
+        &self.names
+    }
+
+    pub fn locals(&self) -> &[Ptr<String>] {
+        &self.variables.locals
+    }
+
+    pub fn parameters(&self) -> usize {
+        self.variables.parameters
+    }
+
+    pub fn cells(&self) -> &[Ptr<String>] {
+        &self.variables.cells
+    }
+
+    pub fn captures(&self) -> &[usize] {
+        &self.variables.captures
+    }
+
+    /// Cells created by every frame running this code.
+    fn own_cells(&self) -> usize {
+        self.cells().len().saturating_sub(self.captures().len())
+    }
+
+    pub fn handlers(&self) -> &[Handler] {
+        &self.handlers
+    }
+
+    pub fn lines(&self) -> &[(usize, usize)] {
+        &self.debug.lines
+    }
+
+    pub fn name(&self) -> &str {
+        &self.debug.name
+    }
+
+    pub fn file(&self) -> &str {
+        &self.debug.file
+    }
+
+    /// The source line of the instruction at `index`, `None` if the code
+    /// was built without a line table.
+    pub fn line(&self, index: usize) -> Option<usize> {
+        let lines = &self.debug.lines;
+        let entry = lines.partition_point(|(start, _)| *start <= index);
+        entry.checked_sub(1).map(|entry| lines[entry].1)
     }
 }
 
+/// How a frame finishes the instruction that made a call, once the call
+/// returns a value to it.
+#[derive(Debug, Clone, Copy)]
+pub enum Continuation {
+    /// Push the next item or jump to the target when the iterator is
+    /// exhausted, see `Instruction::ForIter`.
+    ForIter(usize),
+}
+
 pub struct Frame {
     instruction_count: usize,
     code: Ptr<Code>,
     scope: HashMap<Ptr<String>, Value>,
+    /// Local variables, `None` until the `let` declaring them runs.
+    locals: Vec<Option<Value>>,
+    cells: Vec<Cell>,
     calling_frame: Option<PtrMut<Frame>>,
-    outer_frame: Option<PtrMut<Frame>>,
+    /// Set while waiting for a call whose value isn't simply pushed.
+    continuation: Option<Continuation>,
     stack: Vec<Value>,
 }
 
 impl Frame {
-    pub fn new(
-        code: Ptr<Code>,
-        calling_frame: Option<PtrMut<Frame>>,
-        outer_frame: Option<PtrMut<Frame>>,
-    ) -> Self {
+    pub fn new(code: Ptr<Code>, calling_frame: Option<PtrMut<Frame>>) -> Self {
+        let stack = Vec::with_capacity(code.value().max_stack());
+        let locals = vec![None; code.value().locals().len()];
+        let cells = new_cells(code.value());
+
         Self {
             instruction_count: 0,
             calling_frame,
-            outer_frame,
+            continuation: None,
             scope: HashMap::new(),
-            stack: Vec::new(),
+            locals,
+            cells,
+            stack,
             code,
         }
     }
 
+    /// Start running `code` in this frame, keeping the variables in scope.
+    pub fn load_code(&mut self, code: Ptr<Code>) {
+        self.locals = vec![None; code.value().locals().len()];
+        self.cells = new_cells(code.value());
+        self.code = code;
+        self.instruction_count = 0;
+        self.continuation = None;
+        self.stack.clear();
+    }
+
     pub fn scope(&self) -> &HashMap<Ptr<String>, Value> {
         &self.scope
     }
 
-    pub fn parameters(&self) -> Vec<Ptr<String>> {
-        let count = self.code.value().parameters;
-        self.code.value().names[0..count].to_vec()
+    pub fn parameters(&self) -> usize {
+        self.code.value().parameters()
+    }
+
+    /// The value of a local variable, `None` if it isn't declared yet.
+    pub fn local(&self, slot: SlotIndex) -> Option<Value> {
+        self.locals[slot].clone()
+    }
+
+    pub fn set_local(&mut self, slot: SlotIndex, value: Value) {
+        self.locals[slot] = Some(value);
+    }
+
+    /// A cell of this frame, `None` if the code has fewer cells.
+    pub fn cell(&self, index: CellIndex) -> Option<Cell> {
+        self.cells.get(index).cloned()
+    }
+
+    pub fn code(&self) -> Ptr<Code> {
+        self.code.clone()
     }
 
     pub fn calling_frame(&self) -> Option<PtrMut<Frame>> {
         self.calling_frame.clone()
     }
 
-    pub fn outer_frame(&self) -> Option<PtrMut<Frame>> {
-        self.outer_frame.clone()
+    pub fn set_continuation(&mut self, continuation: Continuation) {
+        self.continuation = Some(continuation);
+    }
+
+    pub fn take_continuation(&mut self) -> Option<Continuation> {
+        self.continuation.take()
     }
 
     pub fn constant(&self, consi: ConstantIndex) -> Value {
@@ -114,10 +290,28 @@ impl Frame {
         self.scope.contains_key(name)
     }
 
+    /// The source line of the instruction that is being executed.
+    pub fn current_location(&self) -> Option<usize> {
+        self.code
+            .value()
+            .line(self.instruction_count.saturating_sub(1))
+    }
+
+    pub fn instruction_count(&self) -> usize {
+        self.instruction_count
+    }
+
     pub fn instruction(&self) -> Instruction {
         self.code.value().instructions[self.instruction_count]
     }
 
+    /// Fetch the current instruction and advance to the next one.
+    pub fn next_instruction(&mut self) -> Instruction {
+        let instruction = self.instruction();
+        self.instruction_count += 1;
+        instruction
+    }
+
     pub fn push(&mut self, value: Value) {
         self.stack.push(value);
     }
@@ -134,12 +328,29 @@ impl Frame {
         self.instruction_count = addr;
     }
 
+    /// Find the handler covering the instruction that was executed last.
+    pub fn handler(&self) -> Option<Handler> {
+        let index = self.instruction_count.checked_sub(1)?;
+        self.code.value().handler(index)
+    }
+
+    pub fn truncate(&mut self, depth: usize) {
+        self.stack.truncate(depth);
+    }
+
     pub fn pop(&mut self) -> Value {
         self.stack
             .pop()
             .expect("stack corruption: no values left to pop")
     }
 
+    pub fn peek(&self) -> Value {
+        self.stack
+            .last()
+            .cloned()
+            .expect("stack corruption: no values left to peek")
+    }
+
     pub fn pop_pair(&mut self) -> (Value, Value) {
         (self.pop(), self.pop())
     }
@@ -151,3 +362,13 @@ impl Frame {
         self.stack.extend(iter)
     }
 }
+
+fn new_cells(code: &Code) -> Vec<Cell> {
+    (0..code.own_cells())
+        .map(|_| {
+            let cell = PtrMut::new(None);
+            gc::track_cell(&cell);
+            cell
+        })
+        .collect()
+}
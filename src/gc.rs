@@ -0,0 +1,389 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use crate::{
+    frame::{Cell, Function},
+    ptr::{Ptr, PtrMut, WeakPtr, WeakPtrMut},
+    value::{Object, Value},
+};
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap::default());
+}
+
+/// Weak references to every container that can be part of a cycle. Values
+/// are still freed by reference counting, the collector only finds the
+/// cycles that counting can't free.
+struct Heap {
+    tracked: Vec<Tracked>,
+    /// Dead entries are dropped when `tracked` grows past this length, a
+    /// weak reference keeps the memory of its container allocated.
+    prune_at: usize,
+    collections: usize,
+}
+
+const MIN_PRUNE_AT: usize = 1024;
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self {
+            tracked: Vec::new(),
+            prune_at: MIN_PRUNE_AT,
+            collections: 0,
+        }
+    }
+}
+
+impl Heap {
+    fn push(&mut self, tracked: Tracked) {
+        if self.tracked.len() >= self.prune_at {
+            self.prune();
+            self.prune_at = (self.tracked.len() * 2).max(MIN_PRUNE_AT);
+        }
+
+        self.tracked.push(tracked);
+    }
+
+    fn prune(&mut self) {
+        self.tracked.retain(Tracked::is_alive);
+    }
+}
+
+enum Tracked {
+    Object(WeakPtrMut<Object>),
+    List(WeakPtrMut<Vec<Value>>),
+    Cell(WeakPtrMut<Option<Value>>),
+    Function(WeakPtr<Function>),
+}
+
+impl Tracked {
+    fn is_alive(&self) -> bool {
+        match self {
+            Self::Object(object) => object.is_alive(),
+            Self::List(list) => list.is_alive(),
+            Self::Cell(cell) => cell.is_alive(),
+            Self::Function(function) => function.is_alive(),
+        }
+    }
+
+    fn upgrade(&self) -> Option<Container> {
+        match self {
+            Self::Object(object) => object.upgrade().map(Container::Object),
+            Self::List(list) => list.upgrade().map(Container::List),
+            Self::Cell(cell) => cell.upgrade().map(Container::Cell),
+            Self::Function(function) => function.upgrade().map(Container::Function),
+        }
+    }
+}
+
+enum Container {
+    Object(PtrMut<Object>),
+    List(PtrMut<Vec<Value>>),
+    Cell(Cell),
+    Function(Ptr<Function>),
+}
+
+impl Container {
+    fn id(&self) -> usize {
+        match self {
+            Self::Object(object) => object.id(),
+            Self::List(list) => list.id(),
+            Self::Cell(cell) => cell.id(),
+            Self::Function(function) => function.id(),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Self::Object(object) => object.strong_count(),
+            Self::List(list) => list.strong_count(),
+            Self::Cell(cell) => cell.strong_count(),
+            Self::Function(function) => function.strong_count(),
+        }
+    }
+
+    fn downgrade(&self) -> Tracked {
+        match self {
+            Self::Object(object) => Tracked::Object(object.downgrade()),
+            Self::List(list) => Tracked::List(list.downgrade()),
+            Self::Cell(cell) => Tracked::Cell(cell.downgrade()),
+            Self::Function(function) => Tracked::Function(function.downgrade()),
+        }
+    }
+
+    /// Call `visit` with the id of every container this one holds a
+    /// reference to. Containers that are borrowed right now are skipped,
+    /// which only makes the collector keep more alive.
+    fn references(&self, visit: &mut impl FnMut(usize)) {
+        match self {
+            Self::Object(object) => {
+                if let Ok(object) = object.try_value() {
+                    for value in object.properties().values() {
+                        value_references(value, visit);
+                    }
+                }
+            }
+            Self::List(list) => {
+                if let Ok(list) = list.try_value() {
+                    for value in list.iter() {
+                        value_references(value, visit);
+                    }
+                }
+            }
+            Self::Cell(cell) => {
+                if let Ok(value) = cell.try_value() {
+                    if let Some(value) = &*value {
+                        value_references(value, visit);
+                    }
+                }
+            }
+            Self::Function(function) => {
+                for cell in function.value().cells() {
+                    visit(cell.id());
+                }
+            }
+        }
+    }
+
+    /// Drop the references held by this container, the values are moved
+    /// to `garbage` so they are freed after every cycle is broken.
+    fn clear(&self, garbage: &mut Vec<Value>) {
+        match self {
+            Self::Object(object) => {
+                if let Ok(mut object) = object.try_value_mut() {
+                    garbage.extend(object.take_properties().into_values());
+                }
+            }
+            Self::List(list) => {
+                if let Ok(mut list) = list.try_value_mut() {
+                    garbage.append(&mut list);
+                }
+            }
+            Self::Cell(cell) => {
+                if let Ok(mut value) = cell.try_value_mut() {
+                    garbage.extend(value.take());
+                }
+            }
+            // A function is freed once the cells of its cycle are cleared.
+            Self::Function(_) => {}
+        }
+    }
+}
+
+fn value_references(value: &Value, visit: &mut impl FnMut(usize)) {
+    match value {
+        Value::Object(object) => visit(object.id()),
+        Value::List(list) => visit(list.id()),
+        Value::Function(function) => visit(function.id()),
+        // Bound values aren't tracked, they are only looked into when
+        // nothing else can reach them.
+        Value::Bound(function, target)
+            if function.strong_count() == 1 && target.strong_count() == 1 =>
+        {
+            value_references(function.value(), visit);
+            value_references(target.value(), visit);
+        }
+        _ => {}
+    }
+}
+
+/// Statistics about the tracked heap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Containers freed by the last collection.
+    pub collected: usize,
+    pub objects: usize,
+    pub lists: usize,
+    pub functions: usize,
+    pub cells: usize,
+    /// Number of collections run so far.
+    pub collections: usize,
+}
+
+/// Track `value` if it is an object, a list or a function.
+pub fn track(value: &Value) {
+    let tracked = match value {
+        Value::Object(object) => Tracked::Object(object.downgrade()),
+        Value::List(list) => Tracked::List(list.downgrade()),
+        Value::Function(function) => Tracked::Function(function.downgrade()),
+        _ => return,
+    };
+
+    HEAP.with(|heap| heap.borrow_mut().push(tracked));
+}
+
+/// Track a cell created for a captured variable.
+pub fn track_cell(cell: &Cell) {
+    HEAP.with(|heap| heap.borrow_mut().push(Tracked::Cell(cell.downgrade())));
+}
+
+/// Statistics about the containers that are alive, without collecting.
+pub fn stats() -> Stats {
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.prune();
+        count(&heap, 0)
+    })
+}
+
+/// Free every container that is only reachable from cycles of tracked
+/// containers.
+///
+/// This is trial deletion: every reference between tracked containers is
+/// subtracted from their reference counts, the containers with references
+/// left are reachable from outside, e.g. from a frame or a Rust value, and
+/// so is everything they reach. The rest is garbage and its cycles are
+/// broken by clearing it.
+pub fn collect() -> Stats {
+    let containers: Vec<Container> = HEAP.with(|heap| {
+        heap.borrow()
+            .tracked
+            .iter()
+            .filter_map(Tracked::upgrade)
+            .collect()
+    });
+
+    let indices: HashMap<usize, usize> = containers
+        .iter()
+        .enumerate()
+        .map(|(index, container)| (container.id(), index))
+        .collect();
+
+    // Our own upgraded reference is not a reference from outside.
+    let mut external: Vec<usize> = containers
+        .iter()
+        .map(|container| container.strong_count() - 1)
+        .collect();
+
+    for container in &containers {
+        container.references(&mut |id| {
+            if let Some(index) = indices.get(&id) {
+                external[*index] = external[*index].saturating_sub(1);
+            }
+        });
+    }
+
+    let mut reachable = vec![false; containers.len()];
+    let mut pending: Vec<usize> = (0..containers.len())
+        .filter(|index| external[*index] > 0)
+        .collect();
+
+    while let Some(index) = pending.pop() {
+        if reachable[index] {
+            continue;
+        }
+
+        reachable[index] = true;
+        containers[index].references(&mut |id| {
+            if let Some(index) = indices.get(&id) {
+                pending.push(*index);
+            }
+        });
+    }
+
+    let mut garbage = Vec::new();
+    let mut collected = 0;
+
+    for (container, reachable) in containers.iter().zip(&reachable) {
+        if !reachable {
+            container.clear(&mut garbage);
+            collected += 1;
+        }
+    }
+
+    let survivors: Vec<Tracked> = containers
+        .iter()
+        .zip(&reachable)
+        .filter(|(_, reachable)| **reachable)
+        .map(|(container, _)| container.downgrade())
+        .collect();
+
+    // Freeing the garbage runs no script code, but it must not happen
+    // while the heap is borrowed.
+    drop(containers);
+    drop(garbage);
+
+    HEAP.with(|heap| {
+        let mut heap = heap.borrow_mut();
+        heap.tracked = survivors;
+        heap.prune_at = (heap.tracked.len() * 2).max(MIN_PRUNE_AT);
+        heap.collections += 1;
+        count(&heap, collected)
+    })
+}
+
+fn count(heap: &Heap, collected: usize) -> Stats {
+    let mut stats = Stats {
+        collected,
+        collections: heap.collections,
+        ..Stats::default()
+    };
+
+    for tracked in &heap.tracked {
+        match tracked {
+            Tracked::Object(_) => stats.objects += 1,
+            Tracked::List(_) => stats.lists += 1,
+            Tracked::Function(_) => stats.functions += 1,
+            Tracked::Cell(_) => stats.cells += 1,
+        }
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Engine;
+
+    #[test]
+    fn collects_a_list_cycle() {
+        collect();
+
+        let list = Value::new_list(Vec::new());
+        let Value::List(ptr) = &list else {
+            unreachable!()
+        };
+        ptr.value_mut().push(list.clone());
+        let weak = ptr.downgrade();
+
+        // Still referenced from here, so nothing is collected.
+        assert_eq!(collect().collected, 0);
+        assert!(weak.upgrade().is_some());
+
+        drop(list);
+        assert_eq!(collect().collected, 1);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn collects_a_closure_cycle() {
+        let mut engine = Engine::new();
+        let collected = engine
+            .eval(
+                "gc.collect();
+                let f = fn() { let g = fn() g; ^ 0; };
+                f();
+                gc.collect().collected",
+            )
+            .expect("script runs");
+
+        // The function of `g` and the cell holding it.
+        assert!(matches!(collected, Value::Integer(2)));
+    }
+
+    #[test]
+    fn keeps_reachable_cycles() {
+        let mut engine = Engine::new();
+        let value = engine
+            .eval(
+                "let a = {};
+                let b = { a: a };
+                a.b = b;
+                gc.collect();
+                a.b.a == a",
+            )
+            .expect("script runs");
+
+        assert!(matches!(value, Value::Bool(true)));
+    }
+}
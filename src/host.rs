@@ -0,0 +1,258 @@
+//! Types defined by the host application. Their values are `Value::Host`
+//! and hold a Rust value, which native functions borrow through `Host`.
+
+use std::{
+    any::{self, Any},
+    cell::{Ref, RefCell, RefMut},
+    marker::PhantomData,
+    rc::Rc,
+};
+
+use crate::{
+    builtins::Builtins,
+    convert::{FromValue, IntoNative},
+    error::{ErrorKind, RuntimeError},
+    ptr::Ptr,
+    value::{Type, Value},
+    Interpreter,
+};
+
+/// The Rust value of a `Value::Host` argument, checked to be a `T`.
+pub struct Host<T> {
+    value: Rc<RefCell<dyn Any>>,
+    ty: PhantomData<T>,
+}
+
+impl<T: Any> Host<T> {
+    /// Borrow the Rust value, fails if it is borrowed mutably already,
+    /// e.g. when a script passes the same value twice.
+    pub fn borrow(&self) -> Result<Ref<'_, T>, RuntimeError> {
+        let value = self.value.try_borrow().map_err(|_| in_use())?;
+        Ok(Ref::map(value, |value| {
+            value.downcast_ref().expect("host value of the wrong type")
+        }))
+    }
+
+    /// Mutably borrow the Rust value, fails if it is borrowed already.
+    pub fn borrow_mut(&self) -> Result<RefMut<'_, T>, RuntimeError> {
+        let value = self.value.try_borrow_mut().map_err(|_| in_use())?;
+        Ok(RefMut::map(value, |value| {
+            value.downcast_mut().expect("host value of the wrong type")
+        }))
+    }
+}
+
+fn in_use() -> RuntimeError {
+    RuntimeError::new(ErrorKind::Borrow, "host value is already in use")
+}
+
+/// The type of a host value can only be checked while it isn't borrowed
+/// mutably, a value that is in use is reported as such.
+impl<T: Any> FromValue for Host<T> {
+    fn from_value(value: &Value, interp: &Interpreter) -> Result<Self, RuntimeError> {
+        if let Value::Host(host, _) = value {
+            if host.try_borrow().map_err(|_| in_use())?.is::<T>() {
+                return Ok(Self {
+                    value: host.clone(),
+                    ty: PhantomData,
+                });
+            }
+        }
+
+        let name = any::type_name::<T>();
+        let name = name.rsplit("::").next().unwrap_or(name);
+        Err(value.expected(name, interp))
+    }
+}
+
+/// Builds a `Type` for host values, e.g.
+///
+/// ```
+/// use san_script::{
+///     host::{Host, TypeBuilder},
+///     value::Value,
+///     Engine,
+/// };
+///
+/// struct Order {
+///     total: i64,
+/// }
+///
+/// let mut engine = Engine::new();
+/// let order = TypeBuilder::new("Order", engine.interpreter().builtins())
+///     .method("total", |order: Host<Order>| order.borrow().map(|order| order.total))
+///     .display(Value::native_fn(|order: Host<Order>| {
+///         order.borrow().map(|order| format!("<order of {}>", order.total))
+///     }))
+///     .build();
+///
+/// engine.register_global("order", Value::new_host(Order { total: 42 }, order));
+/// let total = engine.eval("order.total()").expect("script runs");
+/// assert!(matches!(total, Value::Integer(42)));
+/// ```
+///
+/// Methods are called with the value they are looked up on as the first
+/// argument, like the `$display`, `$add`, ... slots.
+pub struct TypeBuilder {
+    ty: Type,
+}
+
+macro_rules! impl_slots {
+    ($($slot:ident),*) => {
+        $(
+            #[doc = concat!("Set the `$", stringify!($slot), "` slot.")]
+            // Named after the slots, e.g. `add` sets `$add`.
+            #[allow(clippy::should_implement_trait)]
+            pub fn $slot(mut self, value: Value) -> Self {
+                self.ty.$slot = Some(value);
+                self
+            }
+        )*
+    };
+}
+
+impl TypeBuilder {
+    /// Start a type named `name` deriving from the builtin `object` type.
+    pub fn new(name: &str, builtins: &Builtins) -> Self {
+        Self::with_base(name, builtins.types.object.clone())
+    }
+
+    /// Start a type named `name` deriving from `base`, which provides the
+    /// slots and properties that aren't set.
+    pub fn with_base(name: &str, base: Ptr<Type>) -> Self {
+        Self {
+            ty: Type::new(Ptr::new(name.to_string()), base),
+        }
+    }
+
+    /// Add a property, shared by every value of the type.
+    pub fn property(mut self, name: &str, value: Value) -> Self {
+        self.ty.properties.insert(Ptr::new(name.to_string()), value);
+        self
+    }
+
+    /// Add a method, see `Value::native_fn`.
+    pub fn method<F, Args>(self, name: &str, func: F) -> Self
+    where
+        F: IntoNative<Args>,
+    {
+        self.property(name, Value::native_fn(func))
+    }
+
+    impl_slots!(
+        call,
+        add,
+        subtract,
+        multiply,
+        divide,
+        equals,
+        not_equals,
+        less_than,
+        greater_than,
+        less_than_or_equal,
+        greater_than_or_equal,
+        display,
+        get_property,
+        set_property,
+        get_subscript,
+        set_subscript,
+        negate,
+        not,
+        iter,
+        next
+    );
+
+    pub fn build(self) -> Ptr<Type> {
+        Ptr::new(self.ty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{value::ArgPattern, Engine};
+
+    struct Counter {
+        count: i64,
+    }
+
+    fn engine() -> Engine {
+        let mut engine = Engine::new();
+        let counter = TypeBuilder::new("Counter", engine.interpreter().builtins())
+            .method("add", |counter: Host<Counter>, n: i64| {
+                counter.borrow_mut().map(|mut counter| counter.count += n)
+            })
+            .method("count", |counter: Host<Counter>| {
+                counter.borrow().map(|counter| counter.count)
+            })
+            .method("merge", |counter: Host<Counter>, other: Host<Counter>| {
+                let mut counter = counter.borrow_mut()?;
+                counter.count += other.borrow()?.count;
+                Ok(())
+            })
+            .build();
+
+        engine.register_global(
+            "Counter",
+            Value::native_closure(ArgPattern::Exact(0), move |_, _| {
+                Ok(Value::new_host(Counter { count: 0 }, counter.clone()))
+            }),
+        );
+        engine
+    }
+
+    #[test]
+    fn methods() {
+        let mut engine = engine();
+        let count = engine
+            .eval("let a = Counter(); let b = Counter(); a.add(2); b.add(3); a.merge(b); a.count()")
+            .expect("script runs");
+
+        assert!(matches!(count, Value::Integer(5)));
+    }
+
+    #[test]
+    fn aliased_argument() {
+        let mut engine = engine();
+        let message = engine
+            .eval(
+                "let a = Counter(); let m = null; try { a.merge(a); } catch e { m = e.message; } m",
+            )
+            .expect("script runs");
+
+        assert!(matches!(message, Value::String(m) if m.value() == "host value is already in use"));
+
+        let kind = engine
+            .eval("let k = null; try { a.merge(a); } catch e { k = e.type; } k")
+            .expect("script runs");
+        assert!(matches!(kind, Value::String(k) if k.value() == "BorrowError"));
+    }
+
+    #[test]
+    fn convert_borrowed_value() {
+        let mut engine = engine();
+        let value = engine.eval("Counter()").expect("script runs");
+        let interp = engine.interpreter();
+
+        let counter = Host::<Counter>::from_value(&value, interp).expect("a counter");
+        let _borrowed = counter.borrow_mut().expect("not in use");
+
+        // The value is in use, whether or not it has the expected type.
+        for err in [
+            Host::<Counter>::from_value(&value, interp).err(),
+            Host::<String>::from_value(&value, interp).err(),
+        ] {
+            assert_eq!(err.map(|err| err.kind), Some(ErrorKind::Borrow));
+        }
+    }
+
+    #[test]
+    fn wrong_type() {
+        let mut engine = engine();
+        let kind = engine
+            .eval("let a = Counter(); let k = null; try { a.merge(1); } catch e { k = e.type; } k")
+            .expect("script runs");
+
+        assert!(matches!(kind, Value::String(k) if k.value() == "TypeError"));
+    }
+}
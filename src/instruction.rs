@@ -1,19 +1,35 @@
 pub type ConstantIndex = usize;
 pub type NameIndex = usize;
+pub type SlotIndex = usize;
+pub type CellIndex = usize;
 
 #[derive(Debug, Clone, Copy)]
 pub enum Instruction {
     Call(usize),
     JumpFalse(usize),
+    JumpFalseOrPop(usize),
+    JumpTrueOrPop(usize),
+    ForIter(usize),
+    GetIter,
     Jump(usize),
     Return,
+    Throw,
     CreateFunction,
     CreateList(usize),
+    CreateObject(usize),
     Pop,
+    /// Pop a value and print it unless it is null, used by the REPL.
+    Echo,
     LoadConstant(ConstantIndex),
+    LoadLocal(SlotIndex),
+    StoreLocal(SlotIndex),
+    LoadGlobal(NameIndex),
+    StoreGlobal(NameIndex),
+    LoadCell(CellIndex),
+    StoreCell(CellIndex),
+    /// Pop a value and declare it as a variable of the running frame,
+    /// used for module variables.
     Declare(NameIndex),
-    LoadVariable(NameIndex),
-    StoreVariable(NameIndex),
     StoreProperty(NameIndex),
     LoadProperty(NameIndex),
     Exit(usize),
@@ -28,5 +44,7 @@ pub enum Instruction {
     LessThan,
     GreaterThan,
     LessThanOrEqual,
-    GreaterThanOrEqual
+    GreaterThanOrEqual,
+    Negate,
+    Not,
 }
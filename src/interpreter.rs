@@ -1,11 +1,12 @@
-use std::process::exit;
+use std::{collections::HashMap, time::Instant};
 
 use crate::{
-    builtins::Builtins,
-    frame::{Frame, Function},
-    instruction::{ConstantIndex, Instruction, NameIndex},
+    builtins::{exit_error, iterator_method, Builtins},
+    error::{ErrorKind, RuntimeError, TraceEntry},
+    frame::{Continuation, Frame, Function},
+    instruction::{CellIndex, ConstantIndex, Instruction, NameIndex, SlotIndex},
     ptr::{Ptr, PtrMut},
-    value::{ArgPattern, Value},
+    value::{ArgPattern, Object, Type, Value},
 };
 
 enum Action {
@@ -14,10 +15,40 @@ enum Action {
     ReturnNative(Value),
 }
 
+/// Default maximum call depth, see `Interpreter::set_max_depth`.
+pub const DEFAULT_MAX_DEPTH: usize = 1000;
+
+/// Maximum number of nested `call_with_return` calls. Every one of them
+/// recurses on the Rust stack, which allows for a lot fewer calls than
+/// the call depth of script functions.
+const MAX_NATIVE_DEPTH: usize = 200;
+
+/// The deadline is checked once every this many instructions.
+const DEADLINE_INTERVAL: u32 = 1024;
+
 pub struct Interpreter {
     frame: PtrMut<Frame>,
+    /// The root frame, its scope holds the module variables.
+    globals: PtrMut<Frame>,
     builtins: Builtins,
     next_action: Option<Action>,
+    /// Number of calls that are running, frames of script functions and
+    /// native functions re-entering the interpreter.
+    call_depth: usize,
+    max_depth: usize,
+    /// Number of `call_with_return` calls that are running.
+    native_depth: usize,
+    /// Instructions left to run, `None` if there is no limit.
+    fuel: Option<u64>,
+    deadline: Option<Instant>,
+    /// Instructions run since the deadline was checked.
+    ticks: u32,
+    /// Number of nested runs, native functions re-enter the interpreter.
+    runs: usize,
+    /// Whether the outermost run was stopped by a limit.
+    stopped: bool,
+    /// The base frame and call depth of a run stopped by a limit.
+    suspended: Option<(PtrMut<Frame>, usize)>,
 }
 
 #[macro_export]
@@ -33,22 +64,35 @@ macro_rules! get_native_prop {
             if let Some(base) = ty.value().base.clone() {
                 ty = base;
             } else {
-                panic!(
-                    "value of type `{}` does not support `${}`",
-                    $obj.ty($interp.builtins()).value().name.value(),
-                    stringify!($prop)
-                )
+                return Err($crate::error::RuntimeError::new(
+                    $crate::error::ErrorKind::Type,
+                    format!(
+                        "value of type `{}` does not support `${}`",
+                        $obj.ty($interp.builtins()).value().name.value(),
+                        stringify!($prop)
+                    ),
+                ));
             }
         }
     }};
 }
 
+macro_rules! unary_operation {
+    ($prop:ident) => {
+        fn $prop(&mut self) -> Result<(), RuntimeError> {
+            let value = self.frame.value_mut().pop();
+            let prop = get_native_prop!(self, value, $prop);
+            self.call_value(prop, &[value])
+        }
+    };
+}
+
 macro_rules! operation {
     ($prop:ident) => {
-        fn $prop(&mut self) {
+        fn $prop(&mut self) -> Result<(), RuntimeError> {
             let (lhs, rhs) = self.frame.value_mut().pop_pair();
-            let prop = get_native_prop!(self, rhs, $prop);
-            self.call_value(prop, &[lhs, rhs]);
+            let prop = get_native_prop!(self, lhs, $prop);
+            self.call_value(prop, &[lhs, rhs])
         }
     };
 }
@@ -57,50 +101,350 @@ impl Interpreter {
     /// Create a new interpreter with a root frame and builtins instance.
     pub fn new(frame: PtrMut<Frame>, builtins: Builtins) -> Self {
         Self {
+            globals: frame.clone(),
             frame,
             builtins,
             next_action: None,
+            call_depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            native_depth: 0,
+            fuel: None,
+            deadline: None,
+            ticks: 0,
+            runs: 0,
+            stopped: false,
+            suspended: None,
         }
     }
 
+    /// Limit how deep calls may be nested before a `RecursionError` is
+    /// raised. Native functions calling back into the interpreter count
+    /// too, they recurse on the Rust stack.
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    /// Limit the number of instructions to run, `None` removes the limit.
+    /// A run that uses up its budget fails with a `BudgetError` and can be
+    /// continued with `resume` after refueling.
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+    }
+
+    /// Instructions left to run, `None` if there is no limit.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Add `fuel` to a limited budget.
+    pub fn refuel(&mut self, fuel: u64) {
+        if let Some(left) = &mut self.fuel {
+            *left = left.saturating_add(fuel);
+        }
+    }
+
+    /// Stop runs that are still going at `deadline` with a `TimeoutError`,
+    /// `None` removes the deadline. The deadline is checked periodically,
+    /// so a run may overshoot it slightly. Like for the budget, the run
+    /// can be continued with `resume` after moving the deadline.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+        self.ticks = 0;
+    }
+
+    /// Whether a run was stopped by a limit and can be resumed.
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.is_some()
+    }
+
+    /// Continue the run that was stopped by its budget or deadline.
+    ///
+    /// Only runs stopped while running script code can be resumed. When a
+    /// limit is hit in a function called by a native function, the run is
+    /// aborted like for any other error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no run is suspended, see `is_suspended`.
+    pub fn resume(&mut self) -> Result<Value, RuntimeError> {
+        let (base, depth) = self.suspended.take().expect("no suspended run to resume");
+        self.run_from(base, depth)
+    }
+
     /// A reference to the interpreter Builtins.
     pub fn builtins(&self) -> &Builtins {
         &self.builtins
     }
 
-    /// Run interpreter till it halts.
-    pub fn run(&mut self) {
-        loop {
-            let return_value = self.run_frame();
-            self.frame.value_mut().push(return_value);
+    /// The module variable or builtin named `name`.
+    pub fn global(&self, name: &str) -> Option<Value> {
+        let value = self
+            .globals
+            .value()
+            .variable(&Ptr::new(name.to_string()))
+            .cloned();
+
+        value.or_else(|| self.builtins.resolve(name))
+    }
+
+    /// Declare a module variable, so scripts can use `value` by `name`.
+    /// Host applications use this to add their own functions and values,
+    /// see `Value::native_closure`.
+    pub fn register_global(&mut self, name: &str, value: Value) {
+        self.globals
+            .value_mut()
+            .declare(Ptr::new(name.to_string()), value);
+    }
+
+    /// Run interpreter till it halts or fails with a runtime error.
+    pub fn run(&mut self) -> Result<Value, RuntimeError> {
+        // A suspended run that isn't resumed is abandoned.
+        if let Some((base, _)) = self.suspended.take() {
+            self.frame = base;
         }
+        self.call_depth = 0;
+
+        // The root frame is running already, so errors raised
+        // in it are handled by its own exception handlers
+        self.run_frame(1)
     }
 
     /// Call this value and run the interpreter till it returns.
-    pub fn call_with_return(&mut self, value: Value, args: &[Value]) -> Value {
-        self.call_value(value, args);
-        self.run_frame()
+    pub fn call_with_return(
+        &mut self,
+        value: Value,
+        args: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        if self.native_depth >= MAX_NATIVE_DEPTH {
+            return Err(recursion_error());
+        }
+
+        self.enter_call()?;
+        self.call_depth += 1;
+        self.native_depth += 1;
+
+        let result = match self.call_value(value, args) {
+            Ok(()) => self.run_frame(0),
+            Err(err) => Err(err),
+        };
+
+        self.native_depth -= 1;
+        self.call_depth -= 1;
+        result
     }
 
-    fn run_frame(&mut self) -> Value {
+    fn enter_call(&self) -> Result<(), RuntimeError> {
+        if self.call_depth >= self.max_depth {
+            Err(recursion_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Run until the call that is currently pending returns.
+    /// On failure the interpreter is reset to the frame it started from,
+    /// so a native function that re-entered the interpreter can continue.
+    fn run_frame(&mut self, depth: usize) -> Result<Value, RuntimeError> {
+        self.run_from(self.frame.clone(), depth)
+    }
+
+    fn run_from(&mut self, base: PtrMut<Frame>, mut depth: usize) -> Result<Value, RuntimeError> {
+        let call_depth = self.call_depth;
+        self.runs += 1;
+        let result = self.run_until_return(&mut depth);
+        self.runs -= 1;
+
+        match &result {
+            // A suspended run keeps its frames to continue from.
+            Err(_) if std::mem::take(&mut self.stopped) => {
+                self.suspended = Some((base, depth));
+            }
+            Err(_) => {
+                self.frame = base;
+                self.call_depth = call_depth;
+                self.next_action = None;
+            }
+            Ok(_) => {}
+        }
+
+        result
+    }
+
+    /// Calls made by the called frame are run to completion as well,
+    /// so native functions can re-enter the interpreter.
+    fn run_until_return(&mut self, depth: &mut usize) -> Result<Value, RuntimeError> {
         loop {
             if let Some(action) = self.next_action.take() {
                 match action {
-                    Action::Call(frame) => self.frame = frame,
-                    Action::ReturnNative(value) => return value,
+                    Action::Call(frame) => {
+                        self.frame = frame;
+                        self.call_depth += 1;
+                        *depth += 1;
+                    }
+                    Action::ReturnNative(value) if *depth == 0 => return Ok(value),
+                    Action::ReturnNative(value) => self.return_value(value),
                     Action::Return(value) => {
                         let caller = self.frame.value().calling_frame();
-                        if let Some(caller) = caller {
-                            self.frame = caller;
-                            return value;
-                        } else {
-                            self.exit(0);
+                        match caller {
+                            Some(caller) if *depth > 0 => {
+                                self.frame = caller;
+                                self.call_depth -= 1;
+                                *depth -= 1;
+
+                                if *depth == 0 {
+                                    return Ok(value);
+                                }
+
+                                self.return_value(value);
+                            }
+                            // The root frame returned, as in interactive chunks.
+                            _ => return Ok(value),
                         }
                     }
                 }
             }
 
-            self.execute();
+            if let Err(mut err) = self.check_limits() {
+                err.traceback = self.traceback(*depth);
+                // Only the outermost run can be resumed, the Rust frames of
+                // native functions are gone once the error is returned.
+                self.stopped = self.runs == 1;
+                return Err(err);
+            }
+
+            if let Err(err) = self.execute() {
+                self.unwind(err, depth)?;
+            }
+        }
+    }
+
+    /// Hand the value returned by a call to the running frame.
+    fn return_value(&mut self, value: Value) {
+        let mut frame = self.frame.value_mut();
+
+        match frame.take_continuation() {
+            Some(Continuation::ForIter(jmp)) if self.builtins.is_stop(&value) => {
+                frame.jump_absolute(jmp);
+            }
+            Some(Continuation::ForIter(_)) | None => frame.push(value),
+        }
+    }
+
+    /// Use up one instruction of the budget, or fail if a limit is hit.
+    fn check_limits(&mut self) -> Result<(), RuntimeError> {
+        if let Some(deadline) = self.deadline {
+            if self.ticks == 0 && Instant::now() >= deadline {
+                return Err(RuntimeError::new(ErrorKind::Timeout, "deadline exceeded"));
+            }
+
+            self.ticks = (self.ticks + 1) % DEADLINE_INTERVAL;
+        }
+
+        if let Some(fuel) = &mut self.fuel {
+            if *fuel == 0 {
+                return Err(RuntimeError::new(
+                    ErrorKind::Budget,
+                    "instruction budget exhausted",
+                ));
+            }
+
+            *fuel -= 1;
+        }
+
+        Ok(())
+    }
+
+    /// Trace entries of the running frame and its `depth - 1` callers.
+    fn traceback(&self, depth: usize) -> Vec<TraceEntry> {
+        let mut traceback = vec![trace_entry(&self.frame.value())];
+        let mut frame = self.frame.value().calling_frame();
+
+        for _ in 1..depth {
+            let Some(caller) = frame else { break };
+            traceback.push(trace_entry(&caller.value()));
+            frame = caller.value().calling_frame();
+        }
+
+        traceback
+    }
+
+    /// Unwind frames till a handler for `err` is found, frames below
+    /// `depth` zero belong to the caller and are not unwound.
+    fn unwind(&mut self, mut err: RuntimeError, depth: &mut usize) -> Result<(), RuntimeError> {
+        let value = self.error_value(&err);
+
+        while *depth > 0 {
+            // Scripts can't catch the limits set by the host or `exit`.
+            let handler = match err.kind.is_catchable() {
+                true => self.frame.value().handler(),
+                false => None,
+            };
+
+            if let Some(handler) = handler {
+                let mut frame = self.frame.value_mut();
+                // The call the frame was waiting for failed.
+                frame.take_continuation();
+                frame.truncate(handler.depth);
+                frame.push(value);
+                frame.jump_absolute(handler.target);
+                return Ok(());
+            }
+
+            err.traceback.push(trace_entry(&self.frame.value()));
+
+            let caller = self.frame.value().calling_frame();
+            match caller {
+                Some(caller) => {
+                    self.frame = caller;
+                    self.call_depth -= 1;
+                    *depth -= 1;
+                }
+                None => break,
+            }
+        }
+
+        Err(err)
+    }
+
+    /// The script value of an error, native errors are turned into error objects.
+    fn error_value(&self, err: &RuntimeError) -> Value {
+        err.value
+            .clone()
+            .unwrap_or_else(|| self.builtins.error_object(err.kind.name(), &err.message))
+    }
+
+    /// Create a runtime error for a value thrown by a script.
+    /// Error objects keep the kind and message they were created with.
+    fn thrown_error(&self, value: Value) -> RuntimeError {
+        let (kind, message) = match &value {
+            Value::Object(obj) if obj.value().ty().id() == self.builtins.types.error.id() => {
+                let obj = obj.value();
+                let kind = obj
+                    .get_property(&Ptr::new("type".to_string()))
+                    .and_then(|ty| ty.string(self).ok())
+                    .and_then(|ty| ErrorKind::from_name(ty.value()))
+                    .unwrap_or(ErrorKind::Error);
+                let message = obj
+                    .get_property(&Ptr::new("message".to_string()))
+                    .and_then(|message| message.string(self).ok())
+                    .map(|message| message.value().clone())
+                    .unwrap_or_default();
+
+                (kind, message)
+            }
+            Value::String(str) => (ErrorKind::Error, str.value().clone()),
+            _ => (
+                ErrorKind::Error,
+                format!(
+                    "thrown value of type `{}`",
+                    value.ty(&self.builtins).value().name.value()
+                ),
+            ),
+        };
+
+        RuntimeError {
+            value: Some(value),
+            ..RuntimeError::new(kind, message)
         }
     }
 
@@ -108,90 +452,93 @@ impl Interpreter {
         self.frame.value_mut().pop();
     }
 
-    fn call(&mut self, argc: usize) {
+    fn call(&mut self, argc: usize) -> Result<(), RuntimeError> {
         let func = self.frame.value_mut().pop();
         let args: Vec<_> = (0..argc).map(|_| self.frame.value_mut().pop()).collect();
-        self.call_value(func, &args);
+        self.call_value(func, &args)
     }
 
-    fn call_value(&mut self, value: Value, args: &[Value]) {
+    fn call_value(&mut self, value: Value, args: &[Value]) -> Result<(), RuntimeError> {
         match value {
             Value::Function(func) => {
+                self.enter_call()?;
+
                 let mut frame = func.value().as_frame(self.frame.clone());
                 let parameters = frame.parameters();
 
-                if parameters.len() != args.len() {
-                    panic!(
-                        "expected {} args, but found {}",
-                        parameters.len(),
-                        args.len()
-                    );
+                if parameters != args.len() {
+                    return Err(RuntimeError::new(
+                        ErrorKind::Arity,
+                        format!("expected {} args, but found {}", parameters, args.len()),
+                    ));
                 }
 
-                for (param, value) in parameters.into_iter().zip(args) {
-                    frame.declare(param, value.clone());
+                for (slot, value) in args.iter().enumerate() {
+                    frame.set_local(slot, value.clone());
                 }
 
                 self.next_action = Some(Action::Call(PtrMut::new(frame)));
             }
             Value::Native(func, params) => {
-                let ret_val = match params {
-                    ArgPattern::Exact(len) if len != args.len() => {
-                        panic!("expected {} args, but found {}", len, args.len())
-                    }
-                    ArgPattern::Min(min) if args.len() < min => {
-                        panic!("expected at least {} args, but found {}", min, args.len())
-                    }
-                    ArgPattern::Max(max) if args.len() > max => {
-                        panic!(
-                            "expected not more than {} args, but found {}",
-                            max,
-                            args.len()
-                        )
-                    }
-                    ArgPattern::Range(min, max) if (args.len() < min || args.len() > max) => {
-                        panic!(
-                            "expect between {} and {} args, but found {}",
-                            min,
-                            max,
-                            args.len()
-                        )
-                    }
-                    _ => func(self, args),
-                };
+                check_args(&params, args.len())?;
+
+                let ret_val = func(self, args)?;
+                self.next_action = Some(Action::ReturnNative(ret_val));
+            }
+            Value::NativeClosure(func, params) => {
+                check_args(&params, args.len())?;
 
+                let ret_val = func(self, args)?;
                 self.next_action = Some(Action::ReturnNative(ret_val));
             }
             Value::Bound(obj, bound) => {
                 let mut new_args = vec![bound.value().clone()];
                 new_args.extend_from_slice(args);
-                self.call_value(obj.value().clone(), &new_args)
+                self.call_value(obj.value().clone(), &new_args)?;
             }
             _ => {
                 let ty = value.ty(&self.builtins);
+                let call = ty.value().call.clone();
 
-                let call = ty.value().call.clone().unwrap_or_else(|| {
-                    panic!(
-                        "object of type `{}` does not support `$call`",
-                        ty.value().name.value()
+                let call = call.ok_or_else(|| {
+                    RuntimeError::new(
+                        ErrorKind::Type,
+                        format!(
+                            "object of type `{}` does not support `$call`",
+                            ty.value().name.value()
+                        ),
                     )
-                });
+                })?;
 
                 let mut new_args = vec![value];
                 new_args.extend_from_slice(args);
-                self.call_value(call, &new_args);
+                self.call_value(call, &new_args)?;
             }
         }
+
+        Ok(())
     }
 
-    fn create_function(&self) {
+    fn create_function(&self) -> Result<(), RuntimeError> {
         let value = self.frame.value_mut().pop();
 
         if let Value::Code(code) = value {
-            let func = Ptr::new(Function::new(code, self.frame.clone()));
-            self.frame.value_mut().push(Value::Function(func));
+            let cells = {
+                let frame = self.frame.value();
+                code.value()
+                    .captures()
+                    .iter()
+                    .map(|index| frame.cell(*index).ok_or_else(|| missing_cell(*index)))
+                    .collect::<Result<_, _>>()?
+            };
+            let func = Value::new_function(Function::new(code, cells));
+            self.frame.value_mut().push(func);
+            Ok(())
         } else {
-            panic!("invalid value, expected code object");
+            Err(RuntimeError::new(
+                ErrorKind::Type,
+                "invalid value, expected code object",
+            ))
         }
     }
 
@@ -200,41 +547,64 @@ impl Interpreter {
         self.frame.value_mut().push(constant);
     }
 
-    fn resolve_name(&self, name: &Ptr<String>) -> Option<PtrMut<Frame>> {
-        let mut cur_frame = Some(self.frame.clone());
-
-        while let Some(frame) = cur_frame {
-            if frame.value().contains_variable(name) {
-                return Some(frame);
-            }
+    fn load_local(&self, slot: SlotIndex) -> Result<(), RuntimeError> {
+        let value = self.frame.value().local(slot);
+        let value = value.ok_or_else(|| {
+            unresolved_variable(&self.frame.value().code().value().locals()[slot])
+        })?;
 
-            cur_frame = frame.value().outer_frame();
-        }
+        self.frame.value_mut().push(value);
+        Ok(())
+    }
 
-        None
+    fn store_local(&self, slot: SlotIndex) {
+        let mut frame = self.frame.value_mut();
+        let value = frame.pop();
+        frame.set_local(slot, value);
     }
 
-    fn load_variable(&self, namei: NameIndex) {
+    /// Module variables live in the scope of the root frame.
+    fn load_global(&self, namei: NameIndex) -> Result<(), RuntimeError> {
         let name = self.frame.value().name(namei);
+        let value = self.globals.value().variable(&name).cloned();
 
-        if let Some(builtin) = self.builtins.resolve(&*name.value()) {
-            self.frame.value_mut().push(builtin);
-        } else if let Some(frame) = self.resolve_name(&name) {
-            let value = frame.value().variable(&name).unwrap().clone();
+        if let Some(value) = value.or_else(|| self.builtins.resolve(name.value())) {
             self.frame.value_mut().push(value);
+            Ok(())
         } else {
-            panic!("couln't resolve variable named `{}`", name.value());
+            Err(unresolved_variable(&name))
         }
     }
 
-    fn store_variable(&self, namei: NameIndex) {
+    fn store_global(&self, namei: NameIndex) -> Result<(), RuntimeError> {
         let name = self.frame.value().name(namei);
-        if let Some(frame) = self.resolve_name(&name) {
-            let value = self.frame.value_mut().pop();
-            *frame.value_mut().variable_mut(&name).unwrap() = value;
-        } else {
-            panic!("couln't resolve variable named `{}`", name.value());
+
+        if !self.globals.value().contains_variable(&name) {
+            return Err(unresolved_variable(&name));
         }
+
+        let value = self.frame.value_mut().pop();
+        *self.globals.value_mut().variable_mut(&name).unwrap() = value;
+        Ok(())
+    }
+
+    fn load_cell(&self, index: CellIndex) -> Result<(), RuntimeError> {
+        let cell = self.frame.value().cell(index);
+        let value = cell.ok_or_else(|| missing_cell(index))?.value().clone();
+        let value = value.ok_or_else(|| {
+            unresolved_variable(&self.frame.value().code().value().cells()[index])
+        })?;
+
+        self.frame.value_mut().push(value);
+        Ok(())
+    }
+
+    fn store_cell(&self, index: CellIndex) -> Result<(), RuntimeError> {
+        let mut frame = self.frame.value_mut();
+        let value = frame.pop();
+        let cell = frame.cell(index).ok_or_else(|| missing_cell(index))?;
+        *cell.value_mut() = Some(value);
+        Ok(())
     }
 
     fn declare(&self, namei: NameIndex) {
@@ -250,42 +620,80 @@ impl Interpreter {
             list.push(self.frame.value_mut().pop());
         }
 
-        self.frame.value_mut().push(Value::List(PtrMut::new(list)));
+        self.frame.value_mut().push(Value::new_list(list));
     }
 
-    fn execute(&mut self) {
-        let instruction = self.frame.value().instruction();
+    fn create_object(&self, len: usize) -> Result<(), RuntimeError> {
+        let mut properties = HashMap::new();
 
+        for _ in 0..len {
+            let key = self.frame.value_mut().pop().string(self)?;
+            let value = self.frame.value_mut().pop();
+            properties.insert(key, value);
+        }
+
+        let object = Object::new(self.builtins.types.object.clone(), properties);
+        self.frame.value_mut().push(Value::new_object(object));
+
+        Ok(())
+    }
+
+    fn execute(&mut self) -> Result<(), RuntimeError> {
+        let (index, instruction) = {
+            let mut frame = self.frame.value_mut();
+            (frame.instruction_count(), frame.next_instruction())
+        };
+
+        self.execute_instruction(instruction).map_err(|mut err| {
+            err.instruction.get_or_insert((index, instruction));
+            err
+        })
+    }
+
+    fn execute_instruction(&mut self, instruction: Instruction) -> Result<(), RuntimeError> {
         match instruction {
             Instruction::Pop => self.pop(),
-            Instruction::Call(argc) => self.call(argc),
+            Instruction::Echo => self.echo()?,
+            Instruction::Call(argc) => self.call(argc)?,
             Instruction::Return => self.return_statement(),
-            Instruction::CreateFunction => self.create_function(),
+            Instruction::Throw => self.throw()?,
+            Instruction::CreateFunction => self.create_function()?,
             Instruction::LoadConstant(consi) => self.load_constant(consi),
-            Instruction::LoadVariable(namei) => self.load_variable(namei),
-            Instruction::StoreVariable(namei) => self.store_variable(namei),
+            Instruction::LoadLocal(slot) => self.load_local(slot)?,
+            Instruction::StoreLocal(slot) => self.store_local(slot),
+            Instruction::LoadGlobal(namei) => self.load_global(namei)?,
+            Instruction::StoreGlobal(namei) => self.store_global(namei)?,
+            Instruction::LoadCell(index) => self.load_cell(index)?,
+            Instruction::StoreCell(index) => self.store_cell(index)?,
             Instruction::Declare(namei) => self.declare(namei),
-            Instruction::Exit(code) => self.exit(code),
-            Instruction::StoreSubscript => self.store_subscript(),
-            Instruction::LoadSubscript => self.load_subscript(),
-            Instruction::StoreProperty(namei) => self.store_property(namei),
-            Instruction::LoadProperty(namei) => self.load_property(namei),
-            Instruction::Add => self.add(),
-            Instruction::Subtract => self.subtract(),
-            Instruction::Divide => self.divide(),
-            Instruction::Multiply => self.multiply(),
-            Instruction::Equals => self.equals(),
-            Instruction::NotEquals => self.not_equals(),
-            Instruction::GreaterThan => self.greater_than(),
-            Instruction::GreaterThanOrEqual => self.greater_than_or_equal(),
-            Instruction::LessThan => self.less_than(),
-            Instruction::LessThanOrEqual => self.less_than_or_equal(),
+            Instruction::Exit(code) => self.exit(code)?,
+            Instruction::StoreSubscript => self.store_subscript()?,
+            Instruction::LoadSubscript => self.load_subscript()?,
+            Instruction::StoreProperty(namei) => self.store_property(namei)?,
+            Instruction::LoadProperty(namei) => self.load_property(namei)?,
+            Instruction::Add => self.add()?,
+            Instruction::Subtract => self.subtract()?,
+            Instruction::Divide => self.divide()?,
+            Instruction::Multiply => self.multiply()?,
+            Instruction::Equals => self.equals()?,
+            Instruction::NotEquals => self.not_equals()?,
+            Instruction::GreaterThan => self.greater_than()?,
+            Instruction::GreaterThanOrEqual => self.greater_than_or_equal()?,
+            Instruction::LessThan => self.less_than()?,
+            Instruction::LessThanOrEqual => self.less_than_or_equal()?,
             Instruction::Jump(jmp) => self.jump(jmp),
             Instruction::JumpFalse(jmp) => self.jump_false(jmp),
+            Instruction::JumpFalseOrPop(jmp) => self.jump_or_pop(jmp, false),
+            Instruction::JumpTrueOrPop(jmp) => self.jump_or_pop(jmp, true),
+            Instruction::Negate => self.negate()?,
+            Instruction::Not => self.not()?,
+            Instruction::GetIter => self.get_iter()?,
+            Instruction::ForIter(jmp) => self.for_iter(jmp)?,
             Instruction::CreateList(len) => self.create_list(len),
+            Instruction::CreateObject(len) => self.create_object(len)?,
         }
 
-        self.frame.value_mut().jump_relative(1);
+        Ok(())
     }
 
     fn jump(&self, jmp: usize) {
@@ -293,11 +701,81 @@ impl Interpreter {
     }
 
     fn jump_false(&self, jmp: usize) {
-        if !self.frame.value_mut().pop().as_bool(self) {
+        let value = self.frame.value_mut().pop();
+
+        if !value.as_bool() {
             self.frame.value_mut().jump_absolute(jmp);
         }
     }
 
+    /// Jump and keep the top of the stack if its truthiness equals `when`,
+    /// otherwise pop it.
+    fn jump_or_pop(&self, jmp: usize, when: bool) {
+        let value = self.frame.value().peek();
+
+        if value.as_bool() == when {
+            self.frame.value_mut().jump_absolute(jmp);
+        } else {
+            self.frame.value_mut().pop();
+        }
+    }
+
+    fn get_iter(&mut self) -> Result<(), RuntimeError> {
+        let obj = self.frame.value_mut().pop();
+
+        match self.object_method(&obj, "iter", |ty| &ty.iter)? {
+            Some(iter) => self.call_value(iter, &[]),
+            None => {
+                let iter = get_native_prop!(self, obj, iter);
+                self.call_value(iter, &[obj])
+            }
+        }
+    }
+
+    /// Call `$next`, the frame continues once the call returns, see
+    /// `return_value`.
+    fn for_iter(&mut self, jmp: usize) -> Result<(), RuntimeError> {
+        let iterator = self.frame.value().peek();
+
+        match self.object_method(&iterator, "next", |ty| &ty.next)? {
+            Some(next) => self.call_value(next, &[])?,
+            None => {
+                let next = get_native_prop!(self, iterator, next);
+                self.call_value(next, &[iterator])?;
+            }
+        }
+
+        self.frame
+            .value_mut()
+            .set_continuation(Continuation::ForIter(jmp));
+        Ok(())
+    }
+
+    /// The method named `name` of `obj`, if the `slot` of its type is the
+    /// one of plain objects, which calls that method. The method is called
+    /// directly instead, so a limit hit while it runs can be resumed.
+    fn object_method(
+        &self,
+        obj: &Value,
+        name: &str,
+        slot: fn(&Type) -> &Option<Value>,
+    ) -> Result<Option<Value>, RuntimeError> {
+        let mut ty = obj.ty(&self.builtins);
+
+        while slot(ty.value()).is_none() {
+            match ty.value().base.clone() {
+                Some(base) => ty = base,
+                None => return Ok(None),
+            }
+        }
+
+        if ty.id() != self.builtins.types.object.id() {
+            return Ok(None);
+        }
+
+        iterator_method(self, obj, name).map(Some)
+    }
+
     operation!(add);
     operation!(subtract);
     operation!(multiply);
@@ -308,43 +786,68 @@ impl Interpreter {
     operation!(greater_than);
     operation!(greater_than_or_equal);
     operation!(less_than_or_equal);
+    unary_operation!(negate);
+    unary_operation!(not);
 
-    pub fn store_subscript(&mut self) {
+    pub fn store_subscript(&mut self) -> Result<(), RuntimeError> {
         let obj = self.frame.value_mut().pop();
         let subs = self.frame.value_mut().pop();
         let value = self.frame.value_mut().pop();
 
         let set_subscript = get_native_prop!(self, obj, set_subscript);
-        self.call_value(set_subscript, &[obj, subs, value]);
+        self.call_value(set_subscript, &[obj, subs, value])
     }
 
-    pub fn load_subscript(&mut self) {
+    pub fn load_subscript(&mut self) -> Result<(), RuntimeError> {
         let obj = self.frame.value_mut().pop();
         let subs = self.frame.value_mut().pop();
 
         let get_subscript = get_native_prop!(self, obj, get_subscript);
-        self.call_value(get_subscript, &[obj, subs]);
+        self.call_value(get_subscript, &[obj, subs])
     }
 
-    pub fn store_property(&mut self, namei: NameIndex) {
+    pub fn store_property(&mut self, namei: NameIndex) -> Result<(), RuntimeError> {
         let obj = self.frame.value_mut().pop();
         let prop = self.frame.value().name(namei);
         let value = self.frame.value_mut().pop();
 
         let set_property = get_native_prop!(self, obj, set_property);
-        self.call_value(set_property, &[obj, Value::String(prop), value]);
+        self.call_value(set_property, &[obj, Value::String(prop), value])
     }
 
-    pub fn load_property(&mut self, namei: NameIndex) {
+    pub fn load_property(&mut self, namei: NameIndex) -> Result<(), RuntimeError> {
         let obj = self.frame.value_mut().pop();
         let prop = self.frame.value().name(namei);
 
         let get_property = get_native_prop!(self, obj, get_property);
-        self.call_value(get_property, &[obj, Value::String(prop)]);
+        self.call_value(get_property, &[obj, Value::String(prop)])
+    }
+
+    fn echo(&mut self) -> Result<(), RuntimeError> {
+        let value = self.frame.value_mut().pop();
+
+        if !matches!(value, Value::Null) {
+            let print = self.builtins.print.clone();
+            self.call_with_return(print, &[value])?;
+        }
+
+        Ok(())
+    }
+
+    /// `Exit(0)` ends the module like a return from the root frame, other
+    /// codes end the run like the `exit` builtin.
+    fn exit(&mut self, code: usize) -> Result<(), RuntimeError> {
+        if code != 0 {
+            return Err(exit_error(code));
+        }
+
+        self.next_action = Some(Action::Return(Value::Null));
+        Ok(())
     }
 
-    fn exit(&mut self, code: usize) {
-        exit(code as i32)
+    fn throw(&mut self) -> Result<(), RuntimeError> {
+        let value = self.frame.value_mut().pop();
+        Err(self.thrown_error(value))
     }
 
     fn return_statement(&mut self) {
@@ -352,3 +855,406 @@ impl Interpreter {
         self.next_action = Some(Action::Return(ret_val));
     }
 }
+
+fn unresolved_variable(name: &Ptr<String>) -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::Name,
+        format!("couldn't resolve variable named `{}`", name.value()),
+    )
+}
+
+/// Verified code only refers to cells of the frame running it, but a code
+/// object returned by a function can be turned into a function elsewhere.
+fn missing_cell(index: CellIndex) -> RuntimeError {
+    RuntimeError::new(
+        ErrorKind::Name,
+        format!("the current frame has no cell {}", index),
+    )
+}
+
+/// Check the number of arguments passed to a native function.
+fn check_args(params: &ArgPattern, len: usize) -> Result<(), RuntimeError> {
+    let message = match *params {
+        ArgPattern::Exact(exact) if exact != len => {
+            format!("expected {} args, but found {}", exact, len)
+        }
+        ArgPattern::Min(min) if len < min => {
+            format!("expected at least {} args, but found {}", min, len)
+        }
+        ArgPattern::Max(max) if len > max => {
+            format!("expected not more than {} args, but found {}", max, len)
+        }
+        ArgPattern::Range(min, max) if (len < min || len > max) => format!(
+            "expected between {} and {} args, but found {}",
+            min, max, len
+        ),
+        _ => return Ok(()),
+    };
+
+    Err(RuntimeError::new(ErrorKind::Arity, message))
+}
+
+fn recursion_error() -> RuntimeError {
+    RuntimeError::new(ErrorKind::Recursion, "maximum recursion depth exceeded")
+}
+
+fn trace_entry(frame: &Frame) -> TraceEntry {
+    let code = frame.code();
+
+    TraceEntry {
+        function: code.value().name().to_string(),
+        file: code.value().file().to_string(),
+        line: frame.current_location(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, EngineError};
+
+    fn eval(engine: &mut Engine, source: &str) -> Value {
+        engine.eval(source).expect("script runs")
+    }
+
+    fn runtime_error(engine: &mut Engine, source: &str) -> RuntimeError {
+        match engine.eval(source) {
+            Err(EngineError::Runtime(err)) => err,
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("script didn't fail"),
+        }
+    }
+
+    fn trace(err: &RuntimeError) -> Vec<(&str, &str, Option<usize>)> {
+        err.traceback
+            .iter()
+            .map(|entry| (entry.function.as_str(), entry.file.as_str(), entry.line))
+            .collect()
+    }
+
+    #[test]
+    fn traceback() {
+        let mut engine = Engine::new();
+        let err = runtime_error(
+            &mut engine,
+            "let inner = fn() {
+                throw error(\"boom\");
+            };
+            let obj = {
+                run: fn(self) {
+                    inner();
+                },
+            };
+            let call = fn(f) f();
+            call(fn() obj.run());",
+        );
+
+        // Innermost first, functions are named after the variable or
+        // property they are assigned to.
+        assert_eq!(
+            trace(&err),
+            [
+                ("inner", "<eval>", Some(2)),
+                ("run", "<eval>", Some(6)),
+                ("<anonymous>", "<eval>", Some(10)),
+                ("call", "<eval>", Some(9)),
+                ("<module>", "<eval>", Some(10)),
+            ]
+        );
+        assert_eq!(err.line(), Some(2));
+    }
+
+    #[test]
+    fn traceback_of_native_errors() {
+        let mut engine = Engine::new();
+        let err = runtime_error(&mut engine, "let f = fn(x) {\n  ^ x + [];\n};\nf(1);");
+
+        assert_eq!(err.kind, ErrorKind::Type);
+        assert_eq!(
+            trace(&err),
+            [("f", "<eval>", Some(2)), ("<module>", "<eval>", Some(4))]
+        );
+    }
+
+    #[test]
+    fn locals() {
+        let mut engine = Engine::new();
+        let value = eval(
+            &mut engine,
+            "let x = 1; let f = fn(a) { let x = a + 1; x = x * 2; ^ x; }; f(2) + x",
+        );
+
+        assert!(matches!(value, Value::Integer(7)));
+    }
+
+    #[test]
+    fn local_read_before_let() {
+        let mut engine = Engine::new();
+        let err = runtime_error(
+            &mut engine,
+            "let x = 1; let f = fn() { print(x); let x = 2; }; f();",
+        );
+
+        assert_eq!(err.kind, ErrorKind::Name);
+        assert_eq!(err.message, "couldn't resolve variable named `x`");
+
+        let err = runtime_error(
+            &mut engine,
+            "let g = fn() { if false { let y = 1; } ^ y; }; g();",
+        );
+        assert_eq!(err.kind, ErrorKind::Name);
+    }
+
+    #[test]
+    fn cell_read_before_let() {
+        let mut engine = Engine::new();
+        let err = runtime_error(
+            &mut engine,
+            "let f = fn() { let g = fn() w; g(); let w = 1; }; f();",
+        );
+        assert_eq!(err.kind, ErrorKind::Name);
+
+        // Once the `let` ran, the closure sees the value.
+        let value = eval(
+            &mut engine,
+            "let h = fn() { let g = fn() w; let w = 1; ^ g(); }; h()",
+        );
+        assert!(matches!(value, Value::Integer(1)));
+    }
+
+    /// Run `source` with `fuel` instructions at a time, refueling and
+    /// resuming until it finishes, returns the result and the resumes.
+    fn run_refueling(engine: &mut Engine, source: &str, fuel: u64) -> (Value, usize) {
+        engine.interpreter().set_fuel(Some(fuel));
+        let mut result = engine.eval(source);
+        let mut resumes = 0;
+
+        loop {
+            match result {
+                Ok(value) => return (value, resumes),
+                Err(EngineError::Runtime(err)) if err.kind == ErrorKind::Budget => {
+                    assert!(engine.interpreter().is_suspended());
+                    engine.interpreter().refuel(fuel);
+                    result = engine.interpreter().resume().map_err(EngineError::from);
+                    resumes += 1;
+                }
+                Err(err) => panic!("unexpected error: {}", err),
+            }
+        }
+    }
+
+    const RANGE: &str = "let range = fn(n) {
+        let i = 0;
+        ^ {
+            iter: fn(self) self,
+            next: fn(self) {
+                let k = 0;
+                while k < 20 { k = k + 1; }
+                if i >= n { ^ stop; }
+                i = i + 1;
+                ^ i;
+            },
+        };
+    };";
+
+    #[test]
+    fn resume_after_running_out_of_fuel() {
+        let source = "let f = fn(n) { let total = 0; while n > 0 { total = total + n; n = n - 1; } ^ total; }; f(100)";
+
+        let (value, resumes) = run_refueling(&mut Engine::new(), source, 100);
+        assert!(matches!(value, Value::Integer(5050)));
+        assert!(resumes > 0);
+    }
+
+    #[test]
+    fn resume_in_an_iterator_method() {
+        let mut engine = Engine::new();
+        eval(&mut engine, RANGE);
+
+        let source = "let total = 0; for x in range(10) { total = total + x; } total";
+        let (value, resumes) = run_refueling(&mut engine, source, 200);
+        assert!(matches!(value, Value::Integer(55)));
+        assert!(resumes > 0);
+    }
+
+    #[test]
+    fn limits_are_not_catchable() {
+        let mut engine = Engine::new();
+        engine.interpreter().set_fuel(Some(50));
+
+        let err = runtime_error(
+            &mut engine,
+            "let caught = false; try { while true { } } catch e { caught = true; }",
+        );
+        assert_eq!(err.kind, ErrorKind::Budget);
+        assert_eq!(engine.interpreter().fuel(), Some(0));
+        assert!(matches!(engine.global("caught"), Some(Value::Bool(false))));
+    }
+
+    #[test]
+    fn resume_after_the_deadline() {
+        let mut engine = Engine::new();
+        engine.interpreter().set_deadline(Some(Instant::now()));
+
+        let err = runtime_error(&mut engine, "let n = 0; while n < 10 { n = n + 1; } n");
+        assert_eq!(err.kind, ErrorKind::Timeout);
+        assert!(engine.interpreter().is_suspended());
+
+        engine.interpreter().set_deadline(None);
+        let value = engine.interpreter().resume().expect("script runs");
+        assert!(matches!(value, Value::Integer(10)));
+    }
+
+    #[test]
+    fn abandoned_runs_are_not_resumed() {
+        let mut engine = Engine::new();
+        engine.interpreter().set_fuel(Some(10));
+        runtime_error(&mut engine, "while true { }");
+
+        engine.interpreter().set_fuel(None);
+        assert!(matches!(eval(&mut engine, "1 + 1"), Value::Integer(2)));
+        assert!(!engine.interpreter().is_suspended());
+    }
+
+    #[test]
+    fn recursion_error_is_catchable() {
+        let mut engine = Engine::new();
+        engine.interpreter().set_max_depth(50);
+
+        let value = eval(
+            &mut engine,
+            "let f = fn(n) f(n + 1); let kind = null; try { f(0); } catch e { kind = e.type; } kind",
+        );
+        assert!(matches!(value, Value::String(kind) if kind.value() == "RecursionError"));
+
+        // The interpreter is usable after unwinding.
+        let err = runtime_error(&mut engine, "f(0);");
+        assert_eq!(err.kind, ErrorKind::Recursion);
+        assert_eq!(err.traceback.len(), 51);
+        assert!(matches!(eval(&mut engine, "1 + 1"), Value::Integer(2)));
+    }
+
+    #[test]
+    fn iterate_lists_and_strings() {
+        let mut engine = Engine::new();
+
+        let value = eval(
+            &mut engine,
+            "let n = 0; for x in [1, 2, 3] { n = n * 10 + x; } n",
+        );
+        assert!(matches!(value, Value::Integer(123)));
+
+        let value = eval(&mut engine, "let e = 0; for x in [] { e = 1; } e");
+        assert!(matches!(value, Value::Integer(0)));
+
+        // Strings are iterated by character, not by byte.
+        let value = eval(
+            &mut engine,
+            "let s = \"\"; for c in \"hé!\" { s = s + c + \",\"; } s",
+        );
+        assert!(matches!(value, Value::String(s) if s.value() == "h,é,!,"));
+    }
+
+    const COUNT: &str = "let calls = 0;
+    let count = fn(n) {
+        let i = 0;
+        ^ {
+            iter: fn(self) self,
+            next: fn(self) {
+                calls = calls + 1;
+                if i >= n { ^ stop; }
+                i = i + 1;
+                ^ i;
+            },
+        };
+    };";
+
+    #[test]
+    fn iterate_script_objects() {
+        let mut engine = Engine::new();
+        eval(&mut engine, COUNT);
+
+        let value = eval(&mut engine, "let t = 0; for x in count(3) { t = t + x; } t");
+        assert!(matches!(value, Value::Integer(6)));
+
+        // Returning `stop` ends the loop, `next` isn't called again.
+        assert!(matches!(engine.global("calls"), Some(Value::Integer(4))));
+
+        // `iter` may return another object to iterate.
+        let value = eval(
+            &mut engine,
+            "let t = 0; for x in { iter: fn(self) count(2) } { t = t + x; } t",
+        );
+        assert!(matches!(value, Value::Integer(3)));
+    }
+
+    #[test]
+    fn exhaustion_sentinel() {
+        let mut engine = Engine::new();
+
+        assert!(matches!(
+            eval(&mut engine, "stop == stop"),
+            Value::Bool(true)
+        ));
+        assert!(matches!(
+            eval(&mut engine, "stop == null"),
+            Value::Bool(false)
+        ));
+
+        // A loop over an iterator that is already exhausted doesn't run.
+        eval(&mut engine, COUNT);
+        let value = eval(
+            &mut engine,
+            "let c = count(1); for x in c { } let n = 0; for x in c { n = n + 1; } n",
+        );
+        assert!(matches!(value, Value::Integer(0)));
+    }
+
+    #[test]
+    fn iterating_a_value_without_the_protocol() {
+        let mut engine = Engine::new();
+
+        let err = runtime_error(&mut engine, "for x in 5 { }");
+        assert_eq!(err.kind, ErrorKind::Type);
+        assert_eq!(err.message, "`int` is not iterable");
+
+        let err = runtime_error(&mut engine, "for x in { iter: fn(self) null } { }");
+        assert_eq!(err.kind, ErrorKind::Type);
+        assert_eq!(err.message, "`null` is not an iterator");
+    }
+
+    #[test]
+    fn unary_operators() {
+        let mut engine = Engine::new();
+
+        let value = eval(&mut engine, "-9223372036854775808");
+        assert!(matches!(value, Value::Integer(i64::MIN)));
+
+        // A minus before anything but a literal still negates the value.
+        let value = eval(&mut engine, "let x = [2]; -x[0] - -3 + -(4) - --5");
+        assert!(matches!(value, Value::Integer(-8)));
+        assert!(matches!(eval(&mut engine, "-1.5"), Value::Float(v) if v == -1.5));
+        assert!(matches!(eval(&mut engine, "!0"), Value::Bool(true)));
+        assert!(matches!(eval(&mut engine, "!![]"), Value::Bool(false)));
+    }
+
+    #[test]
+    fn mixed_type_operators() {
+        let mut engine = Engine::new();
+
+        // The left operand picks the method, which converts the right
+        // operand to its own type.
+        assert!(matches!(eval(&mut engine, "1.5 + 1"), Value::Float(v) if v == 2.5));
+        assert!(matches!(eval(&mut engine, "1 + 1.5"), Value::Integer(2)));
+        assert!(matches!(eval(&mut engine, "1.5 < 2"), Value::Bool(true)));
+        assert!(matches!(eval(&mut engine, "2 < 2.5"), Value::Bool(false)));
+
+        let err = runtime_error(&mut engine, "\"a\" + 1");
+        assert_eq!(err.kind, ErrorKind::Type);
+        assert_eq!(err.message, "expected string, but found int");
+
+        let err = runtime_error(&mut engine, "1 + \"a\"");
+        assert_eq!(err.kind, ErrorKind::Type);
+        assert_eq!(err.message, "expected number, but found str");
+    }
+}
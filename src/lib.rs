@@ -1,18 +1,29 @@
 pub mod ast;
 pub mod builtins;
+pub mod bytecode;
 mod compiler;
+pub mod convert;
+pub mod diagnostic;
+pub mod disassembler;
+mod engine;
+pub mod error;
 pub mod frame;
-mod instruction;
+pub mod gc;
+pub mod host;
+pub mod instruction;
 mod interpreter;
 pub mod ptr;
-mod value;
+pub mod source;
+pub mod value;
+pub mod verifier;
 
-pub use compiler::CodeBuilder;
+pub use compiler::{CodeBuilder, OptimizationLevel};
+pub use engine::{Engine, EngineError};
 pub use interpreter::Interpreter;
 
 #[allow(clippy::all)]
 pub mod parser {
     use lalrpop_util::lalrpop_mod;
     lalrpop_mod!(san_script);
-    pub use san_script::ModuleParser as Parser;
+    pub use self::san_script::{EvalModuleParser as EvalParser, ModuleParser as Parser};
 }
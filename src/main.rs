@@ -1,38 +1,105 @@
 use std::{
-    env,
-    fs::read_to_string,
-    io::{stdin, Read},
+    env, fs,
+    io::{stdin, IsTerminal, Read},
+    process,
 };
 
 use san_script::{
-    builtins::Builtins,
-    frame::Frame,
-    parser::Parser,
-    ptr::{Ptr, PtrMut},
-    CodeBuilder, Interpreter,
+    bytecode, disassembler::disassemble, frame::Code, Engine, EngineError, OptimizationLevel,
 };
 
+use self::repl::Repl;
+
+mod repl;
+
 fn main() {
-    let mut content = String::new();
+    let mut args: Vec<String> = env::args().skip(1).collect();
 
-    if let Some(path) = env::args().nth(1) {
-        content = read_to_string(path).expect("error while reading source file");
+    let optimization = if take_flag(&mut args, "--no-optimize") {
+        OptimizationLevel::None
     } else {
-        stdin()
-            .read_to_string(&mut content)
-            .expect("error while reading stdin");
+        OptimizationLevel::Basic
+    };
+
+    if args.first().map(String::as_str) == Some("compile") {
+        compile(&args[1..], optimization);
+        return;
+    }
+
+    let interactive = take_flag(&mut args, "-i");
+    let dis = take_flag(&mut args, "--dis");
+    let path = args.pop();
+
+    if interactive || (path.is_none() && !dis && stdin().is_terminal()) {
+        Repl::new(optimization).run();
+        return;
+    }
+
+    let mut engine = Engine::new();
+    engine.set_optimization(optimization);
+
+    if dis {
+        print!("{}", disassemble(&load(&engine, path.as_deref())));
+        return;
+    }
+
+    let result = match path.as_deref() {
+        Some(path) => engine.run_file(path),
+        None => {
+            let code = load(&engine, None);
+            engine.run(code)
+        }
+    };
+
+    match result {
+        Ok(_) => {}
+        Err(EngineError::ExitRequested(code)) => process::exit(code),
+        Err(err) => fail(format!("{:#}", err)),
     }
+}
+
+/// `san-script compile <source> -o <output>`
+fn compile(args: &[String], optimization: OptimizationLevel) {
+    let (source, output) = match args {
+        [source, flag, output] if flag == "-o" => (source, output.clone()),
+        [source] => (source, format!("{}.sanc", source.trim_end_matches(".san"))),
+        _ => fail("usage: san-script compile <source> [-o <output>]"),
+    };
+
+    let mut engine = Engine::new();
+    engine.set_optimization(optimization);
 
-    let mut code_builder = CodeBuilder::new(0);
+    let code = load(&engine, Some(source));
+    let bytes = bytecode::serialize(&code).unwrap_or_else(|err| fail(err));
 
-    let parser = Parser::new();
-    let module = parser.parse("test", &content).unwrap();
+    if let Err(err) = fs::write(&output, bytes) {
+        fail(format!("error while writing `{}`: {}", output, err));
+    }
+}
 
-    code_builder.compile_module(&module);
+/// Load a script or a compiled bytecode file, from stdin if `path` is `None`.
+fn load(engine: &Engine, path: Option<&str>) -> Code {
+    let result = match path {
+        Some(path) => engine.load_file(path),
+        None => {
+            let mut bytes = Vec::new();
+            if let Err(err) = stdin().read_to_end(&mut bytes) {
+                fail(format!("error while reading stdin: {}", err));
+            }
+            engine.load("<stdin>", &bytes)
+        }
+    };
 
-    let code = Ptr::new(code_builder.build());
-    let frame = PtrMut::new(Frame::new(code, None, None));
+    result.unwrap_or_else(|err| fail(err))
+}
+
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let len = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != len
+}
 
-    let mut interpreter = Interpreter::new(frame, Builtins::new());
-    interpreter.run();
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
 }
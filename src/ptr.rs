@@ -1,6 +1,6 @@
 use std::{
-    cell::{Ref, RefCell, RefMut},
-    rc::Rc,
+    cell::{BorrowError, BorrowMutError, Ref, RefCell, RefMut},
+    rc::{Rc, Weak},
 };
 
 #[derive(Hash, PartialEq, Eq)]
@@ -24,6 +24,27 @@ impl<T> Ptr<T> {
     pub fn id(&self) -> usize {
         Rc::as_ptr(&self.0) as usize
     }
+
+    pub fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+
+    pub fn downgrade(&self) -> WeakPtr<T> {
+        WeakPtr(Rc::downgrade(&self.0))
+    }
+}
+
+/// A `Ptr` that doesn't keep its value alive.
+pub struct WeakPtr<T>(Weak<T>);
+
+impl<T> WeakPtr<T> {
+    pub fn upgrade(&self) -> Option<Ptr<T>> {
+        self.0.upgrade().map(Ptr)
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.0.strong_count() > 0
+    }
 }
 
 pub struct PtrMut<T>(Rc<RefCell<T>>);
@@ -39,15 +60,44 @@ impl<T> PtrMut<T> {
         Self(Rc::new(RefCell::new(value)))
     }
 
-    pub fn value(&self) -> Ref<T> {
+    pub fn value(&self) -> Ref<'_, T> {
         self.0.borrow()
     }
 
-    pub fn value_mut(&self) -> RefMut<T> {
+    pub fn value_mut(&self) -> RefMut<'_, T> {
         self.0.borrow_mut()
     }
 
     pub fn id(&self) -> usize {
         Rc::as_ptr(&self.0) as usize
     }
+
+    pub fn try_value(&self) -> Result<Ref<'_, T>, BorrowError> {
+        self.0.try_borrow()
+    }
+
+    pub fn try_value_mut(&self) -> Result<RefMut<'_, T>, BorrowMutError> {
+        self.0.try_borrow_mut()
+    }
+
+    pub fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+
+    pub fn downgrade(&self) -> WeakPtrMut<T> {
+        WeakPtrMut(Rc::downgrade(&self.0))
+    }
+}
+
+/// A `PtrMut` that doesn't keep its value alive.
+pub struct WeakPtrMut<T>(Weak<RefCell<T>>);
+
+impl<T> WeakPtrMut<T> {
+    pub fn upgrade(&self) -> Option<PtrMut<T>> {
+        self.0.upgrade().map(PtrMut)
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.0.strong_count() > 0
+    }
 }
@@ -0,0 +1,289 @@
+use std::{env, path::PathBuf, process};
+
+use rustyline::{error::ReadlineError, DefaultEditor};
+use san_script::{
+    ast::Module,
+    builtins::Builtins,
+    diagnostic::Diagnostic,
+    disassembler::disassemble,
+    frame::{Code, Frame},
+    parser::EvalParser,
+    ptr::{Ptr, PtrMut},
+    CodeBuilder, Interpreter, OptimizationLevel,
+};
+
+const FILE_NAME: &str = "<stdin>";
+
+const HELP: &str = "\
+:dis      show the bytecode of the last input
+:reset    forget all variables
+:history  list previous inputs
+:help     show this message
+:quit     leave the REPL";
+
+/// An interactive session, every input runs in the same root frame so
+/// variables declared by earlier inputs stay in scope.
+pub struct Repl {
+    frame: PtrMut<Frame>,
+    interpreter: Interpreter,
+    last_code: Option<Ptr<Code>>,
+    optimization: OptimizationLevel,
+    /// Line editor, its history is kept in `history_path`.
+    editor: DefaultEditor,
+    history_path: Option<PathBuf>,
+}
+
+impl Repl {
+    pub fn new(optimization: OptimizationLevel) -> Self {
+        let (frame, interpreter) = root();
+        let history_path =
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".san_script_history"));
+
+        let mut editor = DefaultEditor::new().unwrap_or_else(|err| {
+            eprintln!("error while setting up the terminal: {}", err);
+            process::exit(1);
+        });
+        if let Some(path) = &history_path {
+            // There is no history before the first session.
+            let _ = editor.load_history(path);
+        }
+
+        Self {
+            frame,
+            interpreter,
+            last_code: None,
+            optimization,
+            editor,
+            history_path,
+        }
+    }
+
+    pub fn run(&mut self) {
+        let mut input = Input::default();
+
+        loop {
+            let prompt = if input.is_empty() { ">> " } else { ".. " };
+
+            let line = match self.editor.readline(prompt) {
+                Ok(line) => line,
+                // Ctrl-C discards the unfinished input.
+                Err(ReadlineError::Interrupted) => {
+                    input.clear();
+                    continue;
+                }
+                Err(ReadlineError::Eof) => break,
+                Err(err) => {
+                    eprintln!("error while reading stdin: {}", err);
+                    process::exit(1);
+                }
+            };
+
+            if input.is_empty() && line.trim_start().starts_with(':') {
+                if !self.command(line.trim()) {
+                    break;
+                }
+                continue;
+            }
+
+            if let Some(chunk) = input.push_line(&line) {
+                if !chunk.trim().is_empty() {
+                    self.add_history(chunk.trim());
+                    self.eval(&chunk);
+                }
+            }
+        }
+    }
+
+    /// Run a meta command, returns `false` if the REPL should stop.
+    fn command(&mut self, command: &str) -> bool {
+        match command {
+            ":dis" => match &self.last_code {
+                Some(code) => print!("{}", disassemble(code.value())),
+                None => println!("nothing to disassemble"),
+            },
+            ":reset" => {
+                (self.frame, self.interpreter) = root();
+                self.last_code = None;
+            }
+            ":history" => {
+                for (index, entry) in self.editor.history().iter().enumerate() {
+                    println!("{:>4} {}", index + 1, entry);
+                }
+            }
+            ":help" => println!("{}", HELP),
+            ":quit" => return false,
+            _ => println!("unknown command `{}`, see `:help`", command),
+        }
+
+        true
+    }
+
+    fn eval(&mut self, input: &str) {
+        let code = match compile(input, self.optimization) {
+            Ok(code) => Ptr::new(code),
+            Err(err) => {
+                eprintln!("{}", err);
+                return;
+            }
+        };
+
+        self.last_code = Some(code.clone());
+        self.frame.value_mut().load_code(code);
+
+        if let Err(err) = self.interpreter.run() {
+            if let Some(code) = err.exit_code() {
+                process::exit(code);
+            }
+
+            eprintln!("{:#}", err);
+        }
+    }
+
+    fn add_history(&mut self, input: &str) {
+        // Newlines are insignificant, so every entry is kept on one line.
+        let entry = input.lines().map(str::trim).collect::<Vec<_>>().join(" ");
+
+        // History is a convenience, failing to save it is not an error.
+        let _ = self.editor.add_history_entry(entry);
+        if let Some(path) = &self.history_path {
+            let _ = self.editor.save_history(path);
+        }
+    }
+}
+
+fn root() -> (PtrMut<Frame>, Interpreter) {
+    let mut code_builder = CodeBuilder::new(0);
+    let module = Module {
+        name: FILE_NAME.to_string(),
+        body: Vec::new(),
+    };
+    code_builder
+        .compile_interactive(&module, "")
+        .expect("an empty module compiles");
+
+    let code = Ptr::new(code_builder.build());
+    let frame = PtrMut::new(Frame::new(code, None));
+    let interpreter = Interpreter::new(frame.clone(), Builtins::new());
+
+    (frame, interpreter)
+}
+
+/// Compile a chunk of input, the `;` after its last statement may be left
+/// out like in `Engine::eval`.
+fn compile(input: &str, optimization: OptimizationLevel) -> Result<Code, Diagnostic> {
+    let module = EvalParser::new()
+        .parse(FILE_NAME, input)
+        .map_err(|err| Diagnostic::from_parse_error(FILE_NAME, input, &err))?;
+
+    let mut code_builder = CodeBuilder::new(0);
+    code_builder.set_optimization(optimization);
+    code_builder.compile_interactive(&module, input)?;
+    Ok(code_builder.build())
+}
+
+/// The lines of a chunk of input that is being read.
+#[derive(Debug, Default)]
+struct Input {
+    text: String,
+}
+
+impl Input {
+    fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.text.clear();
+    }
+
+    /// Add `line` to the chunk, returns the chunk once its brackets are closed.
+    fn push_line(&mut self, line: &str) -> Option<String> {
+        self.text.push_str(line);
+        self.text.push('\n');
+
+        if is_unbalanced(&self.text) {
+            None
+        } else {
+            Some(std::mem::take(&mut self.text))
+        }
+    }
+}
+
+/// Whether `input` has brackets that are not closed yet.
+fn is_unbalanced(input: &str) -> bool {
+    let mut depth = 0i32;
+    let mut in_string = false;
+
+    for c in input.chars() {
+        match c {
+            '"' => in_string = !in_string,
+            '(' | '[' | '{' if !in_string => depth += 1,
+            ')' | ']' | '}' if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+
+    depth > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use san_script::value::Value;
+
+    #[test]
+    fn unbalanced_brackets() {
+        assert!(!is_unbalanced("let x = [1, (2)];"));
+        assert!(is_unbalanced("let f = fn() {"));
+        assert!(is_unbalanced("let x = [1, {a: (2"));
+        assert!(!is_unbalanced("let f = fn() { ^ 1; };"));
+
+        // Brackets in strings don't count, and closing too many brackets
+        // is left to the parser.
+        assert!(!is_unbalanced("print(\"{[(\");"));
+        assert!(is_unbalanced("print(\")\""));
+        assert!(!is_unbalanced("x)"));
+    }
+
+    #[test]
+    fn multi_line_input() {
+        let mut input = Input::default();
+
+        assert_eq!(input.push_line("let f = fn(n) {"), None);
+        assert!(!input.is_empty());
+        assert_eq!(input.push_line("    ^ n * 2;"), None);
+        let chunk = input.push_line("}").expect("brackets are closed");
+        assert!(input.is_empty());
+        assert_eq!(chunk, "let f = fn(n) {\n    ^ n * 2;\n}\n");
+
+        // The chunk runs without a `;` after its last statement.
+        let (frame, mut interpreter) = root();
+        frame.value_mut().load_code(Ptr::new(
+            compile(&chunk, OptimizationLevel::Basic).expect("valid chunk"),
+        ));
+        interpreter.run().expect("chunk runs");
+
+        let code = compile("let x = f(21)", OptimizationLevel::Basic).expect("valid chunk");
+        frame.value_mut().load_code(Ptr::new(code));
+        interpreter.run().expect("chunk runs");
+        assert!(matches!(interpreter.global("x"), Some(Value::Integer(42))));
+    }
+
+    #[test]
+    fn interrupted_input_is_discarded() {
+        let mut input = Input::default();
+        assert_eq!(input.push_line("let x = ["), None);
+
+        input.clear();
+        assert!(input.is_empty());
+        assert_eq!(input.push_line("1"), Some("1\n".to_string()));
+    }
+
+    #[test]
+    fn syntax_errors_point_into_the_chunk() {
+        match compile("let x = 1;\nlet = 2", OptimizationLevel::Basic) {
+            Err(err) => assert_eq!((err.line, err.column), (2, 5)),
+            Ok(_) => panic!("invalid chunk compiled"),
+        }
+    }
+}
@@ -1,8 +1,14 @@
 use crate::builtins::Builtins;
+use crate::convert::IntoNative;
+use crate::error::{ErrorKind, RuntimeError};
 use crate::frame::{Code, Frame, Function};
+use crate::gc;
 use crate::interpreter::Interpreter;
 use crate::ptr::{Ptr, PtrMut};
+use std::any::Any;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::rc::Rc;
 
 pub struct Type {
     pub name: Ptr<String>,
@@ -23,6 +29,10 @@ pub struct Type {
     pub set_property: Option<Value>,
     pub get_subscript: Option<Value>,
     pub set_subscript: Option<Value>,
+    pub negate: Option<Value>,
+    pub not: Option<Value>,
+    pub iter: Option<Value>,
+    pub next: Option<Value>,
     pub properties: HashMap<Ptr<String>, Value>,
 }
 
@@ -45,6 +55,10 @@ impl Type {
             set_property: None,
             get_subscript: None,
             set_subscript: None,
+            negate: None,
+            not: None,
+            iter: None,
+            next: None,
             less_than_or_equal: None,
             greater_than_or_equal: None,
             greater_than: None,
@@ -70,6 +84,10 @@ impl Object {
         Self { ty, properties }
     }
 
+    pub fn ty(&self) -> Ptr<Type> {
+        self.ty.clone()
+    }
+
     pub fn get_property(&self, name: &Ptr<String>) -> Option<Value> {
         self.properties.get(name).cloned()
     }
@@ -77,9 +95,20 @@ impl Object {
     pub fn set_property(&mut self, name: Ptr<String>, value: Value) {
         self.properties.insert(name, value);
     }
+
+    pub fn properties(&self) -> &HashMap<Ptr<String>, Value> {
+        &self.properties
+    }
+
+    pub(crate) fn take_properties(&mut self) -> HashMap<Ptr<String>, Value> {
+        std::mem::take(&mut self.properties)
+    }
 }
 
-pub type NativeFunction = fn(&mut Interpreter, &[Value]) -> Value;
+pub type NativeFunction = fn(&mut Interpreter, &[Value]) -> Result<Value, RuntimeError>;
+
+/// A native function that can capture state, see `Value::native_closure`.
+pub type NativeClosure = Rc<dyn Fn(&mut Interpreter, &[Value]) -> Result<Value, RuntimeError>>;
 
 #[derive(Clone)]
 pub enum ArgPattern {
@@ -104,10 +133,60 @@ pub enum Value {
     Function(Ptr<Function>),
     Bound(Ptr<Value>, Ptr<Value>),
     Native(NativeFunction, ArgPattern),
+    NativeClosure(NativeClosure, ArgPattern),
     Type(Ptr<Type>),
+    /// A value of the host application with a type defined by it, see
+    /// `host::TypeBuilder`.
+    Host(Rc<RefCell<dyn Any>>, Ptr<Type>),
 }
 
 impl Value {
+    /// Create an object, tracked by the cycle collector.
+    pub fn new_object(object: Object) -> Self {
+        let value = Self::Object(PtrMut::new(object));
+        gc::track(&value);
+        value
+    }
+
+    /// Create a list, tracked by the cycle collector.
+    pub fn new_list(list: Vec<Value>) -> Self {
+        let value = Self::List(PtrMut::new(list));
+        gc::track(&value);
+        value
+    }
+
+    /// Create a function, tracked by the cycle collector.
+    pub fn new_function(function: Function) -> Self {
+        let value = Self::Function(Ptr::new(function));
+        gc::track(&value);
+        value
+    }
+
+    /// Create a host value holding `value`, with the methods of `ty`.
+    /// Values held by it are not seen by the cycle collector.
+    pub fn new_host<T: Any>(value: T, ty: Ptr<Type>) -> Self {
+        Self::Host(Rc::new(RefCell::new(value)), ty)
+    }
+
+    /// Create a native function from a Rust closure, e.g. one holding a
+    /// handle of the host application.
+    pub fn native_closure<F>(args: ArgPattern, func: F) -> Self
+    where
+        F: Fn(&mut Interpreter, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    {
+        Self::NativeClosure(Rc::new(func), args)
+    }
+
+    /// Create a native function from a Rust function with typed arguments,
+    /// e.g. `fn(i64, String) -> bool`. The arity is the number of arguments
+    /// and values of the wrong type raise a `TypeError`, see `convert`.
+    pub fn native_fn<F, Args>(func: F) -> Self
+    where
+        F: IntoNative<Args>,
+    {
+        func.into_native()
+    }
+
     pub fn ty(&self, builtins: &Builtins) -> Ptr<Type> {
         match self {
             Self::Object(obj) => obj.value().ty.clone(),
@@ -119,94 +198,95 @@ impl Value {
             Self::Bool(_) => builtins.types.bool.clone(),
             Self::Function(_) => builtins.types.function.clone(),
             Self::Frame(_) => builtins.types.frame.clone(),
-            Self::Native(..) => builtins.types.native.clone(),
+            Self::Native(..) | Self::NativeClosure(..) => builtins.types.native.clone(),
             Self::Code(_) => builtins.types.code.clone(),
             Self::Null => builtins.types.null.clone(),
             Self::Type(_) => builtins.types.ty.clone(),
+            Self::Host(_, ty) => ty.clone(),
         }
     }
 
-    pub fn as_bool(&self, interp: &Interpreter) -> bool {
+    /// Whether this value counts as true in a condition. `null`, `false`,
+    /// zero and empty strings and lists are false, everything else is true.
+    /// `optimizer::truthiness` folds constants the same way.
+    pub fn as_bool(&self) -> bool {
         match self {
             Self::Integer(v) => *v != 0,
             Self::Float(v) => *v != 0.0,
             Self::Bool(b) => *b,
-            _ => panic!(
-                "expected bool, but found {}",
-                self.ty(interp.builtins()).value().name.value()
-            ),
+            Self::Null => false,
+            Self::String(v) => !v.value().is_empty(),
+            Self::List(v) => !v.value().is_empty(),
+            _ => true,
         }
     }
 
-    pub fn list(&self, interp: &Interpreter) -> PtrMut<Vec<Value>> {
+    pub fn object(&self, interp: &Interpreter) -> Result<PtrMut<Object>, RuntimeError> {
         match self {
-            Self::List(v) => v.clone(),
-            _ => panic!(
-                "expected list, but found {}",
-                self.ty(interp.builtins()).value().name.value()
-            ),
+            Self::Object(v) => Ok(v.clone()),
+            _ => Err(self.expected("object", interp)),
         }
     }
 
-    pub fn string(&self, interp: &Interpreter) -> Ptr<String> {
+    pub fn list(&self, interp: &Interpreter) -> Result<PtrMut<Vec<Value>>, RuntimeError> {
         match self {
-            Self::String(v) => v.clone(),
-            _ => panic!(
-                "expected string, but found {}",
-                self.ty(interp.builtins()).value().name.value()
-            ),
+            Self::List(v) => Ok(v.clone()),
+            _ => Err(self.expected("list", interp)),
         }
     }
 
-    pub fn bool(&self, interp: &Interpreter) -> bool {
+    pub fn string(&self, interp: &Interpreter) -> Result<Ptr<String>, RuntimeError> {
         match self {
-            Self::Bool(v) => *v,
-            _ => panic!(
-                "expected bool, but found {}",
-                self.ty(interp.builtins()).value().name.value()
-            ),
+            Self::String(v) => Ok(v.clone()),
+            _ => Err(self.expected("string", interp)),
         }
     }
 
-    pub fn int(&self, interp: &Interpreter) -> i64 {
+    pub fn bool(&self, interp: &Interpreter) -> Result<bool, RuntimeError> {
         match self {
-            Self::Integer(v) => *v,
-            _ => panic!(
-                "expected int, but found {}",
-                self.ty(interp.builtins()).value().name.value()
-            ),
+            Self::Bool(v) => Ok(*v),
+            _ => Err(self.expected("bool", interp)),
         }
     }
 
-    pub fn as_float(&self, interp: &Interpreter) -> f64 {
+    pub fn int(&self, interp: &Interpreter) -> Result<i64, RuntimeError> {
         match self {
-            Value::Integer(v) => *v as f64,
-            Value::Float(v) => *v,
-            _ => panic!(
-                "expected number, but found {}",
-                self.ty(interp.builtins()).value().name.value()
-            ),
+            Self::Integer(v) => Ok(*v),
+            _ => Err(self.expected("int", interp)),
         }
     }
 
-    pub fn float(&self, interp: &Interpreter) -> f64 {
+    pub fn as_float(&self, interp: &Interpreter) -> Result<f64, RuntimeError> {
         match self {
-            Value::Float(v) => *v,
-            _ => panic!(
-                "expected int, but found {}",
-                self.ty(interp.builtins()).value().name.value()
-            ),
+            Value::Integer(v) => Ok(*v as f64),
+            Value::Float(v) => Ok(*v),
+            _ => Err(self.expected("number", interp)),
+        }
+    }
+
+    pub fn float(&self, interp: &Interpreter) -> Result<f64, RuntimeError> {
+        match self {
+            Value::Float(v) => Ok(*v),
+            _ => Err(self.expected("float", interp)),
         }
     }
 
-    pub fn as_int(&self, interp: &Interpreter) -> i64 {
+    pub fn as_int(&self, interp: &Interpreter) -> Result<i64, RuntimeError> {
         match self {
-            Value::Integer(v) => *v,
-            Value::Float(v) => *v as i64,
-            _ => panic!(
-                "expected number argument but found {}",
+            Value::Integer(v) => Ok(*v),
+            Value::Float(v) => Ok(*v as i64),
+            _ => Err(self.expected("number", interp)),
+        }
+    }
+
+    pub(crate) fn expected(&self, expected: &str, interp: &Interpreter) -> RuntimeError {
+        RuntimeError::new(
+            ErrorKind::Type,
+            format!(
+                "expected {}, but found {}",
+                expected,
                 self.ty(interp.builtins()).value().name.value()
             ),
-        }
+        )
     }
 }
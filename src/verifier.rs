@@ -0,0 +1,467 @@
+use std::fmt;
+
+use crate::{frame::Code, instruction::Instruction, value::Value};
+
+/// Why a `Code` object was rejected by `verify`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    /// Index of the offending instruction, `None` for errors about the
+    /// code object as a whole.
+    pub index: Option<usize>,
+    pub message: String,
+}
+
+impl VerifyError {
+    fn new(index: Option<usize>, message: impl Into<String>) -> Self {
+        Self {
+            index,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+
+        if let Some(index) = self.index {
+            write!(f, " (at instruction {})", index)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Check that `code` can run without making the interpreter panic and
+/// return the maximum depth of its stack.
+///
+/// Every operand must be in range, the stack depth must be the same on
+/// every path to an instruction and never drop below zero, and every
+/// path must end in `Return`, `Exit` or `Throw`. Code objects in the
+/// constants were verified when they were created.
+pub fn verify(code: &Code) -> Result<usize, VerifyError> {
+    let instructions = code.instructions();
+    let len = instructions.len();
+
+    if code.parameters() > code.locals().len() {
+        return Err(VerifyError::new(
+            None,
+            format!(
+                "{} parameters, but only {} locals",
+                code.parameters(),
+                code.locals().len()
+            ),
+        ));
+    }
+
+    if code.captures().len() > code.cells().len() {
+        return Err(VerifyError::new(
+            None,
+            format!(
+                "{} captures, but only {} cells",
+                code.captures().len(),
+                code.cells().len()
+            ),
+        ));
+    }
+
+    for constant in code.constants() {
+        if let Value::Code(nested) = constant {
+            let nested = nested.value();
+
+            // Functions are created by the frame running this code, so
+            // their captures refer to its cells.
+            if let Some(index) = nested
+                .captures()
+                .iter()
+                .find(|index| **index >= code.cells().len())
+            {
+                return Err(VerifyError::new(
+                    None,
+                    format!("nested code `{}` captures cell {}", nested.name(), index),
+                ));
+            }
+        }
+    }
+
+    for handler in code.handlers() {
+        if handler.start > handler.end || handler.end > len || handler.target >= len {
+            return Err(VerifyError::new(
+                None,
+                format!("handler {:?} out of range", handler),
+            ));
+        }
+    }
+
+    if len == 0 {
+        return Err(VerifyError::new(None, "code has no instructions"));
+    }
+
+    let mut flow = Flow {
+        depths: vec![None; len],
+        pending: Vec::new(),
+    };
+    let mut max_depth = 0;
+    flow.enter(0, 0, 0)?;
+
+    while let Some((index, depth)) = flow.pending.pop() {
+        let instruction = instructions[index];
+
+        check_operands(code, index, instruction)?;
+
+        let (pops, pushes) = stack_effect(instruction);
+        if depth < pops {
+            return Err(VerifyError::new(
+                Some(index),
+                format!(
+                    "`{:?}` pops {} values from a stack of {}",
+                    instruction, pops, depth
+                ),
+            ));
+        }
+
+        let after = depth - pops + pushes;
+        max_depth = max_depth.max(after);
+
+        // An error raised here continues at the handler with the stack
+        // truncated to the depth of the handler and the error pushed.
+        // Operands may be popped already when the error is raised.
+        for handler in code.handlers() {
+            if handler.start <= index && index < handler.end {
+                if handler.depth > depth - pops {
+                    return Err(VerifyError::new(
+                        Some(index),
+                        format!(
+                            "handler expects a stack of {}, found {}",
+                            handler.depth,
+                            depth - pops
+                        ),
+                    ));
+                }
+
+                max_depth = max_depth.max(handler.depth + 1);
+                flow.enter(index, handler.target, handler.depth + 1)?;
+            }
+        }
+
+        match instruction {
+            Instruction::Return | Instruction::Exit(_) | Instruction::Throw => {}
+            Instruction::Jump(target) => flow.enter(index, target, after)?,
+            Instruction::JumpFalse(target) => {
+                flow.enter(index, target, after)?;
+                flow.enter(index, index + 1, after)?;
+            }
+            // The condition stays on the stack when jumping.
+            Instruction::JumpFalseOrPop(target) | Instruction::JumpTrueOrPop(target) => {
+                flow.enter(index, target, depth)?;
+                flow.enter(index, index + 1, after)?;
+            }
+            // An exhausted iterator stays on the stack when jumping.
+            Instruction::ForIter(target) => {
+                flow.enter(index, target, depth)?;
+                flow.enter(index, index + 1, after)?;
+            }
+            _ => flow.enter(index, index + 1, after)?,
+        }
+    }
+
+    Ok(max_depth)
+}
+
+/// Stack depths found so far and instructions still to be checked.
+struct Flow {
+    depths: Vec<Option<usize>>,
+    pending: Vec<(usize, usize)>,
+}
+
+impl Flow {
+    /// Continue from the instruction at `from` to `target` with a stack
+    /// of `depth` values.
+    fn enter(&mut self, from: usize, target: usize, depth: usize) -> Result<(), VerifyError> {
+        match self.depths.get(target) {
+            None => Err(VerifyError::new(
+                Some(from),
+                "control flows past the last instruction",
+            )),
+            Some(None) => {
+                self.depths[target] = Some(depth);
+                self.pending.push((target, depth));
+                Ok(())
+            }
+            Some(Some(known)) if *known == depth => Ok(()),
+            Some(Some(known)) => Err(VerifyError::new(
+                Some(target),
+                format!(
+                    "stack depth is {} or {} depending on the path",
+                    known, depth
+                ),
+            )),
+        }
+    }
+}
+
+fn check_operands(code: &Code, index: usize, instruction: Instruction) -> Result<(), VerifyError> {
+    let out_of_range = |what: &str, operand: usize| {
+        Err(VerifyError::new(
+            Some(index),
+            format!("{} {} out of range", what, operand),
+        ))
+    };
+
+    match instruction {
+        Instruction::LoadConstant(consi) if consi >= code.constants().len() => {
+            out_of_range("constant", consi)
+        }
+        Instruction::Declare(namei)
+        | Instruction::LoadProperty(namei)
+        | Instruction::StoreProperty(namei)
+        | Instruction::LoadGlobal(namei)
+        | Instruction::StoreGlobal(namei)
+            if namei >= code.names().len() =>
+        {
+            out_of_range("name", namei)
+        }
+        Instruction::LoadLocal(slot) | Instruction::StoreLocal(slot)
+            if slot >= code.locals().len() =>
+        {
+            out_of_range("local", slot)
+        }
+        Instruction::LoadCell(index) | Instruction::StoreCell(index)
+            if index >= code.cells().len() =>
+        {
+            out_of_range("cell", index)
+        }
+        Instruction::Jump(target)
+        | Instruction::JumpFalse(target)
+        | Instruction::JumpFalseOrPop(target)
+        | Instruction::JumpTrueOrPop(target)
+        | Instruction::ForIter(target)
+            if target >= code.instructions().len() =>
+        {
+            out_of_range("jump target", target)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The number of values `instruction` pops and pushes when it continues
+/// with the next instruction.
+fn stack_effect(instruction: Instruction) -> (usize, usize) {
+    match instruction {
+        Instruction::Call(argc) => (argc.saturating_add(1), 1),
+        Instruction::JumpFalse(_) => (1, 0),
+        Instruction::JumpFalseOrPop(_) | Instruction::JumpTrueOrPop(_) => (1, 0),
+        Instruction::ForIter(_) => (1, 2),
+        Instruction::GetIter => (1, 1),
+        Instruction::Jump(_) => (0, 0),
+        Instruction::Return | Instruction::Throw => (1, 0),
+        Instruction::CreateFunction => (1, 1),
+        Instruction::CreateList(len) => (len, 1),
+        Instruction::CreateObject(len) => (len.saturating_mul(2), 1),
+        Instruction::Pop | Instruction::Echo => (1, 0),
+        Instruction::LoadConstant(_)
+        | Instruction::LoadLocal(_)
+        | Instruction::LoadGlobal(_)
+        | Instruction::LoadCell(_) => (0, 1),
+        Instruction::StoreLocal(_)
+        | Instruction::StoreGlobal(_)
+        | Instruction::StoreCell(_)
+        | Instruction::Declare(_) => (1, 0),
+        // Setters are called like functions and push their result.
+        Instruction::StoreProperty(_) => (2, 1),
+        Instruction::StoreSubscript => (3, 1),
+        Instruction::LoadProperty(_) => (1, 1),
+        Instruction::LoadSubscript => (2, 1),
+        Instruction::Exit(_) => (0, 0),
+        Instruction::Add
+        | Instruction::Subtract
+        | Instruction::Multiply
+        | Instruction::Divide
+        | Instruction::Equals
+        | Instruction::NotEquals
+        | Instruction::LessThan
+        | Instruction::GreaterThan
+        | Instruction::LessThanOrEqual
+        | Instruction::GreaterThanOrEqual => (2, 1),
+        Instruction::Negate | Instruction::Not => (1, 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        frame::{DebugInfo, Handler, Variables},
+        ptr::Ptr,
+    };
+
+    use Instruction::*;
+
+    /// Code with one constant and one local.
+    fn code(instructions: Vec<Instruction>, handlers: Vec<Handler>) -> Result<Code, VerifyError> {
+        code_with_constants(instructions, vec![Value::Null], handlers)
+    }
+
+    fn code_with_constants(
+        instructions: Vec<Instruction>,
+        constants: Vec<Value>,
+        handlers: Vec<Handler>,
+    ) -> Result<Code, VerifyError> {
+        Code::new(
+            instructions,
+            constants,
+            Vec::new(),
+            Variables {
+                locals: vec![Ptr::new("x".to_string())],
+                ..Variables::default()
+            },
+            handlers,
+            DebugInfo::default(),
+        )
+    }
+
+    fn error(instructions: Vec<Instruction>, handlers: Vec<Handler>) -> VerifyError {
+        code(instructions, handlers)
+            .err()
+            .expect("code is rejected")
+    }
+
+    #[test]
+    fn computes_max_stack() {
+        let code = code(
+            vec![
+                LoadConstant(0),
+                LoadConstant(0),
+                LoadConstant(0),
+                Add,
+                Add,
+                Return,
+            ],
+            Vec::new(),
+        )
+        .expect("valid code");
+
+        assert_eq!(code.max_stack(), 3);
+    }
+
+    #[test]
+    fn rejects_empty_code() {
+        assert_eq!(error(Vec::new(), Vec::new()).index, None);
+    }
+
+    #[test]
+    fn rejects_stack_underflow() {
+        let err = error(vec![LoadConstant(0), Add, Return], Vec::new());
+        assert_eq!(err.index, Some(1));
+    }
+
+    #[test]
+    fn rejects_mismatched_depths() {
+        // The stack holds one more value at `Return` when the jump isn't taken.
+        let err = error(
+            vec![
+                LoadConstant(0),
+                JumpFalse(4),
+                LoadConstant(0),
+                LoadConstant(0),
+                Return,
+            ],
+            Vec::new(),
+        );
+        assert_eq!(err.index, Some(4));
+    }
+
+    #[test]
+    fn rejects_falling_off_the_end() {
+        let err = error(vec![LoadConstant(0)], Vec::new());
+        assert_eq!(err.index, Some(0));
+    }
+
+    #[test]
+    fn rejects_operands_out_of_range() {
+        assert_eq!(
+            error(vec![LoadConstant(1), Return], Vec::new()).index,
+            Some(0)
+        );
+        assert_eq!(error(vec![LoadLocal(1), Return], Vec::new()).index, Some(0));
+        assert_eq!(error(vec![LoadCell(0), Return], Vec::new()).index, Some(0));
+        assert_eq!(
+            error(vec![LoadConstant(0), Jump(5)], Vec::new()).index,
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn rejects_nested_captures_out_of_range() {
+        let nested = Code::new(
+            vec![LoadCell(0), Return],
+            Vec::new(),
+            Vec::new(),
+            Variables {
+                cells: vec![Ptr::new("y".to_string())],
+                captures: vec![0],
+                ..Variables::default()
+            },
+            Vec::new(),
+            DebugInfo::default(),
+        )
+        .expect("valid code");
+
+        let err = code_with_constants(
+            vec![LoadConstant(0), CreateFunction, Return],
+            vec![Value::Code(Ptr::new(nested))],
+            Vec::new(),
+        )
+        .err()
+        .expect("code is rejected");
+        assert_eq!(err.index, None);
+    }
+
+    #[test]
+    fn accepts_handlers() {
+        // The handler continues with the error pushed on an empty stack.
+        let code = code(
+            vec![LoadConstant(0), Throw, Return],
+            vec![Handler {
+                start: 0,
+                end: 2,
+                target: 2,
+                depth: 0,
+            }],
+        )
+        .expect("valid code");
+
+        assert_eq!(code.max_stack(), 1);
+    }
+
+    #[test]
+    fn rejects_handlers_out_of_range() {
+        let handler = |start, end, target| Handler {
+            start,
+            end,
+            target,
+            depth: 0,
+        };
+        let instructions = || vec![LoadConstant(0), Return];
+
+        assert_eq!(error(instructions(), vec![handler(0, 2, 2)]).index, None);
+        assert_eq!(error(instructions(), vec![handler(0, 3, 1)]).index, None);
+        assert_eq!(error(instructions(), vec![handler(2, 1, 1)]).index, None);
+    }
+
+    #[test]
+    fn rejects_handlers_deeper_than_the_stack() {
+        let err = error(
+            vec![LoadConstant(0), Throw, Return],
+            vec![Handler {
+                start: 0,
+                end: 2,
+                target: 2,
+                depth: 1,
+            }],
+        );
+        assert_eq!(err.index, Some(0));
+    }
+}
@@ -0,0 +1,85 @@
+use std::{
+    fs,
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+/// Run the `san-script` binary with `source` on stdin.
+fn run(source: &str) -> Output {
+    run_with(&[], source)
+}
+
+fn run_with(args: &[&str], source: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_san-script"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("start san-script");
+
+    child
+        .stdin
+        .take()
+        .expect("piped stdin")
+        .write_all(source.as_bytes())
+        .expect("write script");
+
+    child.wait_with_output().expect("run san-script")
+}
+
+#[test]
+fn exit_status() {
+    assert_eq!(run("print(1);").status.code(), Some(0));
+    assert_eq!(run("exit(7);").status.code(), Some(7));
+    assert_eq!(
+        run("try { exit(3); } finally { exit(4); }").status.code(),
+        Some(3)
+    );
+    assert_eq!(run("throw \"x\";").status.code(), Some(1));
+}
+
+#[test]
+fn exit_code_out_of_range() {
+    let output = run("exit(4294967297);");
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).expect("utf-8 output");
+    assert!(stderr.contains("ValueError: exit code 4294967297 doesn't fit in 32 bits"));
+}
+
+#[test]
+fn run_script_file() {
+    let dir = std::env::temp_dir().join(format!("san-script-cli-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("temporary directory");
+    let path = dir.join("script.san");
+    fs::write(
+        &path,
+        "let f = fn() { throw \"oops\"; };\nprint(\"ran\");\nf();\n",
+    )
+    .expect("write script");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_san-script"))
+        .arg(&path)
+        .output()
+        .expect("run san-script");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ran\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("File \"{}\", line 3, in <module>", path.display())));
+    assert!(stderr.contains("Error: oops"));
+
+    fs::remove_dir_all(&dir).expect("remove temporary directory");
+}
+
+#[test]
+fn no_optimize() {
+    let source = "if false { print(1); }";
+    let listing =
+        |args: &[&str]| String::from_utf8(run_with(args, source).stdout).expect("utf-8 output");
+
+    // Optimization is on by default, the dead branch is left out.
+    assert!(!listing(&["--dis"]).contains("print"));
+    assert!(listing(&["--dis", "--no-optimize"]).contains("print"));
+}